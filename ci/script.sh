@@ -1,16 +1,178 @@
 set -ex
 
+# Generates code from $svd into the test crate and builds it; the arguments
+# are passed to svd2rust
 test_gen() {
-    echo 'extern crate volatile_register;' > $td/src/lib.rs
-    cross run --target $TARGET --release -- -i $td/$svd $1 >> $td/src/lib.rs
-    cross build --manifest-path $td --target $TARGET
+    # NOTE the generated code refers to `core`
+    echo '#![no_std]' > $td/src/lib.rs
+    echo 'extern crate volatile_register;' >> $td/src/lib.rs
+    cross run --target $TARGET --release -- -i $td/$svd "$@" >> $td/src/lib.rs
+    cross build --manifest-path $td/Cargo.toml --target $TARGET
+}
+
+# Generates the register maps of $svd as a crate with `--output-dir` and
+# builds it with the features listed in $features; the arguments are passed
+# to svd2rust
+test_output_dir() {
+    cross run --target $TARGET --release -- \
+          -i $td/$svd --output-dir $td/out/src "$@"
+    cross build --manifest-path $td/out/Cargo.toml --target $TARGET \
+          --features "$features"
+}
+
+# Maps the register block of GPIOA from a file in a temporary directory
+# through the `linux` module of the code generated with `--linux`
+test_linux() {
+    cross init --lib --name bar $td/linux
+    cat >> $td/linux/Cargo.toml <<EOF
+volatile-register = "0.1.0"
+libc = { version = "0.2.18", optional = true }
+
+[features]
+linux = ["libc"]
+EOF
+    cross run --target $TARGET --release -- \
+          -i $td/$svd --linux --output-dir $td/linux/src
+
+    mkdir -p $td/linux/tests
+    cat > $td/linux/tests/map.rs <<'EOF'
+extern crate bar;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+
+use bar::Peripherals;
+
+const GPIOA: u64 = 0x4800_0000;
+
+#[test]
+fn map() {
+    let dir = env::temp_dir().join("svd2rust-linux");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mem");
+    // sparse file that covers the register block of GPIOA
+    File::create(&path).unwrap().set_len(GPIOA + 0x400).unwrap();
+
+    let p = Peripherals::take().unwrap();
+    {
+        let mut gpioa = p.GPIOA.map(&path).unwrap();
+        unsafe { gpioa.odr.write_bits(0xa5) }
+        assert_eq!(gpioa.odr.read_bits(), 0xa5);
+    }
+
+    // ODR is at offset 0x14
+    let mut odr = [0; 4];
+    let mut file = File::open(&path).unwrap();
+    file.seek(SeekFrom::Start(GPIOA + 0x14)).unwrap();
+    file.read_exact(&mut odr).unwrap();
+    assert_eq!(odr, [0xa5, 0, 0, 0]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+EOF
+    cross test --manifest-path $td/linux/Cargo.toml --target $TARGET \
+          --features linux
+    rm -rf $td/linux
+}
+
+# Checks that the `sim` module of the code generated with `--bus` gives the
+# accesses to the registers the side effects that the SVD file describes
+test_sim() {
+    cross init --lib --name qux $td/sim
+    cat >> $td/sim/Cargo.toml <<EOF
+volatile-register = "0.1.0"
+
+[features]
+sim = []
+EOF
+    cat > $td/sim.svd <<'EOF'
+<device>
+  <name>SIM</name>
+  <size>32</size>
+  <peripherals>
+    <peripheral>
+      <name>TIM</name>
+      <baseAddress>0x40000000</baseAddress>
+      <registers>
+        <register>
+          <name>SR</name>
+          <description>Status register</description>
+          <addressOffset>0x0</addressOffset>
+          <fields>
+            <field>
+              <name>UIF</name>
+              <description>Update flag</description>
+              <bitOffset>0</bitOffset>
+              <bitWidth>1</bitWidth>
+              <modifiedWriteValues>oneToClear</modifiedWriteValues>
+            </field>
+            <field>
+              <name>OVR</name>
+              <description>Overrun flag</description>
+              <bitOffset>1</bitOffset>
+              <bitWidth>1</bitWidth>
+              <readAction>clear</readAction>
+            </field>
+            <field>
+              <name>EN</name>
+              <description>Enable</description>
+              <bitOffset>8</bitOffset>
+              <bitWidth>1</bitWidth>
+            </field>
+          </fields>
+        </register>
+      </registers>
+    </peripheral>
+  </peripherals>
+</device>
+EOF
+    cross run --target $TARGET --release -- \
+          -i $td/sim.svd --bus --output-dir $td/sim/src
+
+    mkdir -p $td/sim/tests
+    cat > $td/sim/tests/side_effects.rs <<'EOF'
+extern crate qux;
+
+use qux::{sim, Peripherals};
+
+const SR: usize = 0x4000_0000;
+
+#[test]
+fn side_effects() {
+    let mut p = Peripherals::take().unwrap();
+
+    // the hardware raises the write-1-to-clear flag
+    sim::poke(SR, 0b01);
+
+    // writing a zero leaves it alone
+    p.TIM.sr.write(|w| w.en(true));
+    assert_eq!(sim::peek(SR), 0x101);
+
+    // writing a one clears it
+    p.TIM.sr.write(|w| w.en(true).clear_uif());
+    assert_eq!(sim::peek(SR), 0x100);
+
+    // the hardware raises the clear-on-read flag; reading it clears it
+    sim::poke(SR, 0x102);
+    assert!(p.TIM.sr.read_with_side_effects().ovr());
+    assert!(!p.TIM.sr.read_with_side_effects().ovr());
+    assert_eq!(sim::peek(SR), 0x100);
+
+    sim::reset();
+    assert_eq!(sim::peek(SR), 0);
+}
+EOF
+    cross test --manifest-path $td/sim/Cargo.toml --target $TARGET \
+          --features sim
+    rm -rf $td/sim
 }
 
 main() {
     cross build --target $TARGET
     cross build --target $TARGET --release
 
-    if [ -n $DISABLE_TESTS ]; then
+    if [ -n "$DISABLE_TESTS" ]; then
         return
     fi
 
@@ -30,6 +192,17 @@ main() {
     cross init --name foo $td
     echo 'volatile-register = "0.1.0"' >> $td/Cargo.toml
 
+    # test crate for `--output-dir`
+    cross init --lib --name baz $td/out
+    cat >> $td/out/Cargo.toml <<EOF
+volatile-register = "0.1.0"
+
+[features]
+mock = []
+rt = []
+sim = []
+EOF
+
     curl -L \
          https://raw.githubusercontent.com/posborne/cmsis-svd/python-0.4/data/STMicro/STM32F30x.svd \
          > $td/STM32F30x.svd
@@ -55,16 +228,41 @@ main() {
     test_gen tim3
     test_gen tim6
 
+    # Test the whole device modes
+    test_gen --device
+    test_gen --device --generic
+    test_gen --device --shared
+    test_gen --device --self-contained
+    test_gen --device --generic --shared --self-contained
+    test_gen --device --bus
+    test_gen --device --linux
+
+    features=
+    test_output_dir
+    test_output_dir --generic --shared
+    for features in mock sim; do
+        test_output_dir --bus
+    done
+    test_sim
+
     # Test register arrays
     svd=nrf51.svd
     test_gen
     test_gen gpio
     test_gen timer
+    test_gen --device
 
     # japaric/svd2rust#42
     svd=LPC43xx_svd_v5.svd
     test_gen
     test_gen sct
+    test_gen --device
+
+    # the `linux` module maps files with `mmap64`, which macOS doesn't have
+    if [ $TRAVIS_OS_NAME = linux ]; then
+        svd=STM32F30x.svd
+        test_linux
+    fi
 
     rm -rf $td
 }
@@ -42,6 +42,57 @@
 //!     /// 0x0c - APB2 peripheral reset register (RCC_APB2RSTR)
 //! ```
 //!
+//! - Generate the register maps of all the peripherals of a device. Each
+//!   peripheral gets its own module and a handle that owns its register block
+//!   (see [Peripherals](#peripherals)).
+//!
+//! ```
+//! $ svd2rust -i STM32F30x.svd --device | head
+//! /// General-purpose I/Os
+//! pub struct GPIOA {
+//!     _marker: ::core::marker::PhantomData<*const ()>,
+//! }
+//! unsafe impl Send for GPIOA {}
+//! impl GPIOA {
+//!     /// Returns a pointer to the register block
+//!     pub fn ptr() -> *const gpioa::Gpioa {
+//!         0x48000000 as *const _
+//! ```
+//!
+//! - Generate the same register maps as a crate: a `lib.rs` crate root plus
+//!   one file per peripheral, named after the module of the peripheral. The
+//!   files whose contents didn't change are not rewritten. The generated
+//!   files are listed in a `.svd2rust-files` manifest; the next run removes
+//!   the files that the manifest lists but that it no longer generates.
+//!
+//! ```
+//! $ svd2rust -i STM32F30x.svd --output-dir src
+//! $ ls src | head -3
+//! adc1_2.rs
+//! adc1.rs
+//! adc2.rs
+//! ```
+//!
+//! - Generate a linker script that provides the addresses of the register
+//!   blocks (see [Register block](#register-block)).
+//!
+//! ```
+//! $ svd2rust -i STM32F30x.svd --emit linker | head -3
+//! GPIOA_REGISTER_BLOCK = 0x48000000;
+//! GPIOB_REGISTER_BLOCK = 0x48000400;
+//! GPIOC_REGISTER_BLOCK = 0x48000800;
+//! ```
+//!
+//! - Generate the linker script fragment that goes with the `Interrupt`
+//!   enumeration and the vector table (see [Interrupts](#interrupts)).
+//!
+//! ```
+//! $ svd2rust -i STM32F30x.svd --emit device-x | head -3
+//! PROVIDE(WWDG = DefaultHandler);
+//! PROVIDE(PVD = DefaultHandler);
+//! PROVIDE(TAMP_STAMP = DefaultHandler);
+//! ```
+//!
 //! # API
 //!
 //! The `svd2rust` generates the following API for each peripheral:
@@ -79,6 +130,28 @@
 //! }
 //! ```
 //!
+//! Register arrays whose elements are contiguous are laid out as Rust arrays,
+//! e.g. `pub ccr: [Ccr; 4]`. If the SVD file names the elements of the array
+//! (`dimIndex`) then each element also gets a pair of accessors, e.g.
+//! `afrl()` / `afrl_mut()`. The elements of register arrays that are *not*
+//! contiguous are exposed as individual fields plus a pair of accessors that
+//! take the index of the element, e.g. `dmar(n)` / `dmar_mut(n)`.
+//!
+//! Registers that overlap with a previous register, e.g. the input and output
+//! modes of a timer's `CCMR1` register, are not fields of the register block.
+//! Instead, the register block gets a pair of accessors, e.g. `ccmr1_input()`
+//! / `ccmr1_input_mut()`, that return an alternate view of the location owned
+//! by the field. The documentation of the field lists its alternate views.
+//!
+//! Register clusters are register blocks of their own, defined in a module
+//! named after the cluster, e.g. `dma1::ch::Ch`, that are fields of the
+//! enclosing register block. Arrays of clusters are laid out as Rust arrays,
+//! e.g. `pub ch: [ch::Ch; 7]`, where each element is padded to the stride of
+//! the array (`dimIncrement`).
+//!
+//! `svd-parser` doesn't parse the clusters; `peripheral_extras` extracts them
+//! from the SVD file and `Options` passes them to `gen_device_with`.
+//!
 //! The user has to "instantiate" this definition for each peripheral the
 //! microcontroller has. They have two choices:
 //!
@@ -105,6 +178,14 @@
 //! This has the side effect that the `I2C1` and `I2C2` symbols get "taken" so
 //! no other C/Rust symbol (`static`, `function`, etc.) can have the same name.
 //!
+//! `svd2rust --emit linker` generates this linker script, and `svd2rust
+//! --emit externs` generates the matching `extern` block, for all the
+//! peripherals of a device. The declarations refer to the register block
+//! types that `--device` generates. Their symbols have a `_REGISTER_BLOCK`
+//! suffix, e.g. `I2C1_REGISTER_BLOCK`, because the interrupt handlers, e.g.
+//! `extern "C" fn I2C1_EV()` or `extern "C" fn TIM2()`, take the names of
+//! the peripherals.
+//!
 //! - "constructor" functions. Example below:
 //!
 //! ``` rust
@@ -122,6 +203,94 @@
 //! }
 //! ```
 //!
+//! ## Peripherals
+//!
+//! When the register maps of a whole device are generated (`--device`), each
+//! peripheral also gets a zero sized handle, e.g. `GPIOA`, that dereferences to
+//! its register block. The handles are owned by a `Peripherals` struct which
+//! can only be obtained once:
+//!
+//! ``` rust
+//! // `take` returns `None` if it has already been called
+//! let mut p = Peripherals::take().unwrap();
+//!
+//! // `GPIOA` derefs to `gpioa::Gpioa`
+//! p.GPIOA.moder.modify(|_, w| w.moder0().output());
+//! ```
+//!
+//! `Peripherals::steal` is the `unsafe`, unchecked version of `take`. The
+//! handles also provide a pointer to their register block through their `ptr`
+//! method.
+//!
+//! Peripherals that have the same registers, e.g. the ones that derive from
+//! another peripheral, share their register block type. The module of the
+//! first of these peripherals defines the type; the modules of the others
+//! re-export its contents and alias the type:
+//!
+//! ``` rust
+//! pub mod gpiob {
+//!     pub use super::gpioa::*;
+//!
+//!     /// Register block of GPIOB; same type as `gpioa::Gpioa`
+//!     pub type Gpiob = super::gpioa::Gpioa;
+//! }
+//! ```
+//!
+//! So code that works with `&gpioa::Gpioa` works with all the GPIO ports.
+//!
+//! A peripheral that has a `dim`, e.g. `UART%s`, is expanded into one
+//! peripheral per instance, `UART0`, `UART1`, etc., which share their register
+//! block type. A function named after the peripheral returns the register
+//! block of the instance selected at runtime, or `None` if there's no such
+//! instance:
+//!
+//! ``` rust
+//! /// Returns the register block of the `n`-th instance of `UART%s` (`n` in
+//! /// `0..8`, stride 0x400)
+//! pub fn uart(n: usize) -> Option<&'static uart0::Uart0> { .. }
+//! ```
+//!
+//! `svd-parser` doesn't parse the `dim` of peripherals; `parse` extracts them
+//! from the SVD file and `Options` passes them to `gen_device`.
+//!
+//! A peripheral that derives from another one takes the registers of its base
+//! peripheral, plus the ones it lists itself; a register that has the same name
+//! as a register of the base peripheral replaces it. In that case the register
+//! block types of the two peripherals differ. Registers, fields and enumerated
+//! values can also derive from other ones. `resolve` applies these rules to a
+//! `Device`, and `parse` to the registers and fields; chains of `derivedFrom`
+//! are followed and cycles are reported as errors.
+//!
+//! ## Interrupts
+//!
+//! `gen_device` also generates an `Interrupt` enumeration with one variant per
+//! interrupt of the device. The `nr` method of the `Nr` trait returns the
+//! number of the interrupt as a `u8`; the SVD files whose interrupt numbers
+//! don't fit in it are reported as errors. A peripheral can have several
+//! interrupts and several peripherals can share one; the interrupts that
+//! share a name but not a number, or a number but not a name, are reported as
+//! errors too.
+//!
+//! ``` rust
+//! /// Enumeration of all the interrupts
+//! pub enum Interrupt {
+//!     /// 0 - Window Watchdog interrupt
+//!     WWDG,
+//!     /// 1 - PVD through EXTI line detection interrupt
+//!     PVD,
+//!     ..
+//! }
+//! ```
+//!
+//! When the `rt` feature of the generated crate is enabled, the crate also
+//! provides the interrupts part of the vector table, which is placed in the
+//! `.vector_table.interrupts` linker section. The table refers to one handler
+//! per interrupt, e.g. `extern "C" fn WWDG()`. The application can define
+//! these handlers using `#[no_mangle]`; the handlers it doesn't define default
+//! to `DefaultHandler` through the linker script fragment that `svd2rust
+//! --emit device-x` generates. This fragment has to be included in the linker
+//! script of the application, which must also define `DefaultHandler`.
+//!
 //! ## `read` / `modify` / `write`
 //!
 //! Each register in the register block, e.g. the `cr1` field in the `I2c`
@@ -133,6 +302,12 @@
 //! - write-only registers only expose the `write` method.
 //! - read-write registers expose all the methods: `read`, `modify` and
 //!   `write`.
+//! - write-once registers only expose the `write_once` method.
+//! - read-write-once registers expose the `read` and `write_once` methods.
+//!
+//! `write_once` works like `write` but its name makes it explicit that the
+//! register can only be written once after a reset; the hardware ignores the
+//! subsequent writes.
 //!
 //! This is signature of each of these methods:
 //!
@@ -237,6 +412,243 @@
 //! // TOGGLE the STOP bit
 //! i2c1.cr2.modify(|r, w| w.stop(!r.stop()));
 //! ```
+//!
+//! The fields whose `modifiedWriteValues` give writes a side effect, e.g.
+//! `oneToClear` flags, are written with the value that leaves them unchanged
+//! by `modify` and `write`, regardless of what was read or of the reset
+//! value. Their writers gain a method that performs the side effect instead:
+//!
+//! ``` rust
+//! // Clear the UIF flag, a `oneToClear` field, but not the other flags of SR
+//! tim2.sr.modify(|_, w| w.clear_uif());
+//! ```
+//!
+//! Reading a register, or one of its fields, that has a `readAction` has side
+//! effects, e.g. it clears the flags that were read. The readers of these
+//! registers are named `read_with_side_effects` and
+//! `read_bits_with_side_effects` instead, and they have no `modify` method
+//! because it would trigger the side effects behind the back of the caller.
+//!
+//! ``` rust
+//! // Reading DR pops a value from the receive FIFO
+//! let data = usart1.dr.read_with_side_effects().dr();
+//! ```
+//!
+//! ## Enumerated values
+//!
+//! If a field has `enumeratedValues` in the SVD file then its possible values
+//! are listed in an `enum`. The name of the `enum` is the name of the register
+//! followed by the name of the field, e.g. `ModerModer0`. If the named values
+//! don't cover all the possible values of a readable field then the `enum`
+//! also gets a `_Reserved` variant that holds the raw bits. So does the `enum`
+//! of a readable field that's narrower than its type, e.g. a 2-bit field read
+//! as an `u8`, so that every raw value maps to a variant.
+//!
+//! The reader of such field returns a proxy that exposes the raw value, the
+//! value as an `enum` variant and one `is_*` method per variant:
+//!
+//! ``` rust
+//! if gpioa.moder.read().moder0().is_output() {
+//!     // something
+//! }
+//!
+//! match gpioa.moder.read().moder0().variant() {
+//!     ModerModer0::Input => { .. }
+//!     ..
+//! }
+//! ```
+//!
+//! The writer of such field also returns a proxy that has one method per
+//! variant. Each of these methods writes the value of the variant to the
+//! field:
+//!
+//! ``` rust
+//! gpioa.moder.modify(|_, w| w.moder0().output());
+//! ```
+//!
+//! The proxy also has a `bits` (or `bit`) method to write raw bits to the
+//! field.
+//!
+//! ## Write constraints
+//!
+//! If the `writeConstraint` of a field says that some of its values are not
+//! valid, e.g. the values outside a `range` or the ones that are not
+//! `enumeratedValues`, then the field is written through a proxy whose `bits`
+//! (or `bit`) method is `unsafe`. Writing an enumerated value stays safe, and
+//! the fields constrained to a range get a `checked_bits` (or `checked_bit`)
+//! method that only writes the valid values:
+//!
+//! ``` rust
+//! // PSC must be between 1 and 9
+//! tim2.cr1.modify(|_, w| w.psc().checked_bits(4).unwrap());
+//! ```
+//!
+//! ## Generic registers
+//!
+//! With the `--generic` flag (`Options.generic` in the library) each register
+//! is no longer a struct with its own copy of the `read` / `modify` / `write`
+//! methods. Instead, the crate root gets a single generic register type,
+//! `Reg<SPEC>`, whose methods are implemented once, and each register gets a
+//! zero sized specification type that carries its width, its access and its
+//! reset value:
+//!
+//! ``` rust
+//! /// Specification of the register `CR2`
+//! pub struct Cr2Spec;
+//!
+//! impl super::RegisterSpec for Cr2Spec {
+//!     type Ux = u32;
+//!     ..
+//! }
+//!
+//! impl super::Readable for Cr2Spec {}
+//! impl super::Writable for Cr2Spec {}
+//! impl super::Rewritable for Cr2Spec {}
+//!
+//! pub type Cr2 = super::Reg<Cr2Spec>;
+//! pub type Cr2R = super::R<Cr2Spec>;
+//! pub type Cr2W = super::W<Cr2Spec>;
+//! ```
+//!
+//! The readers and the writers of the fields are inherent methods of
+//! `R<Cr2Spec>` and `W<Cr2Spec>` so the API doesn't change, except that the
+//! `write` method of write-only registers takes `&mut self`. Registers that
+//! have no fields use `RawReg<SPEC>`, whose `read` and `write` methods work
+//! with raw values. The specifications of the registers whose reads have side
+//! effects implement `ReadableWithSideEffects` instead of `Readable`.
+//!
+//! ## Shared registers
+//!
+//! The methods that write to a register take `&mut self` so writing to a
+//! register block requires a mutable reference to it. With the `--shared`
+//! flag (`Options.shared` in the library) the registers are stored in a
+//! `VolatileCell`, a private type of the crate root, and all their methods
+//! take `&self`:
+//!
+//! ``` rust
+//! fn set_high(gpioa: &gpioa::Gpioa) {
+//!     gpioa.bsrr.write(|w| w.bs0(true));
+//! }
+//! ```
+//!
+//! `VolatileCell` is not `Sync` and neither are the register blocks, so
+//! sharing them between an interrupt handler and the rest of the program
+//! still requires some form of synchronization, e.g. a critical section.
+//!
+//! ## Dependencies
+//!
+//! The registers are stored in the `RO`, `RW` and `WO` types of the
+//! `volatile_register` crate, which the crate that contains the generated code
+//! must depend on. With the `--self-contained` flag (`Options.self_contained`
+//! in the library) they are stored in `VolatileCell`s instead, and the
+//! generated code has no dependencies; the API doesn't change. The
+//! `--volatile-path` option (`Options.volatile_path`) names another crate, or
+//! module, that provides the `RO`, `RW` and `WO` types, e.g. a vendored copy
+//! of `volatile_register`:
+//!
+//! ```
+//! $ svd2rust -i STM32F30x.svd --device --volatile-path ::vendored_volatile
+//! ```
+//!
+//! A path that starts with `::` names a crate; with `--output-dir` the crate
+//! root declares it with `extern crate`. Any other path names a module
+//! relative to the crate root, e.g. `vendored::volatile`, which the crate
+//! must define.
+//!
+//! ## Register bus
+//!
+//! With the `--bus` flag (`Options.bus` in the library) the registers are read
+//! and written through the `Bus` type, which implements the `RegisterBus`
+//! trait:
+//!
+//! ``` rust
+//! pub trait RegisterBus {
+//!     fn read(address: usize, width: u8) -> u64;
+//!     fn write(address: usize, width: u8, value: u64);
+//! }
+//! ```
+//!
+//! `Bus` is `VolatileBus` unless the `mock` feature of the generated crate is
+//! enabled. `VolatileBus` uses volatile operations so each register access
+//! still compiles down to a single load or store. The `mock` feature, which
+//! requires `std`, selects `mock::MockBus` instead. This in-memory bus records
+//! the accesses, and returns the values queued with `mock::push_read` or else
+//! the last value written to each register, so drivers can be tested on the
+//! host. The peripheral handles then point to host memory that stands in for
+//! the register blocks, and the mock translates the addresses back to the
+//! physical ones:
+//!
+//! ``` rust
+//! mock::push_read(0x4800_0010, 1);
+//! assert!(p.GPIOA.idr.read().idr0());
+//! assert_eq!(mock::take_accesses(),
+//!            [Access::Read { address: 0x4800_0010, width: 32, value: 1 }]);
+//! ```
+//!
+//! The `sim` feature selects `sim::SimBus`, which simulates the registers
+//! according to the SVD file instead. Each peripheral module gets a `MODEL`
+//! that lists the reset value of each register, the bits that can be read and
+//! the bits that can be written. The registers start with their reset value,
+//! the bits that can't be read, e.g. the reserved bits, read as zero, the
+//! writes to the bits that can't be written are ignored, and so are the
+//! writes to `writeOnce` registers after the first one. `sim::poke` changes
+//! a register like the hardware would and `sim::peek` returns its whole value,
+//! so tests can run a driver and then check the state of the registers:
+//!
+//! ``` rust
+//! p.GPIOA.moder.modify(|_, w| w.moder0().output());
+//! assert_eq!(sim::peek(0x4800_0000), 0x2800_0001);
+//! ```
+//!
+//! The model also lists the bits whose `modifiedWriteValues` or `readAction`
+//! give accesses a side effect, so write-1-to-clear, toggle and clear-on-read
+//! bits behave like in the hardware. Accesses to the addresses that have no
+//! register, e.g. the gaps between registers, don't panic; these addresses
+//! behave as plain memory that resets to zero. Like the mock, the simulation
+//! uses host memory for the register blocks and physical addresses for the
+//! registers.
+//!
+//! ## Linux
+//!
+//! With the `--linux` flag (`Options.linux` in the library) the generated
+//! crate gets a `linux` feature, which requires `std`, for programs that
+//! access the peripherals from Linux user space. The handles of the
+//! peripherals get a `map` method that consumes the handle and maps its
+//! register block from a file like `/dev/mem`, at its physical address:
+//!
+//! ``` rust
+//! let p = Peripherals::take().unwrap();
+//! let mut gpioa: linux::Block<gpioa::Gpioa> = try!(p.GPIOA.map("/dev/mem"));
+//! gpioa.moder.modify(|_, w| w.moder0().output());
+//! ```
+//!
+//! `Block` dereferences to the register block and unmaps it when dropped.
+//! `linux::Mapping` maps any region of a file, e.g. the memory regions of a
+//! UIO device, and its `block` and `block_mut` methods return the register
+//! blocks it contains, which can't outlive the `Mapping`:
+//!
+//! ``` rust
+//! let mut mapping = try!(linux::Mapping::new("/dev/uio0", 0, 0x1000));
+//! let gpioa: &mut gpioa::Gpioa = unsafe { mapping.block_mut(0) };
+//! ```
+//!
+//! The `linux` module maps the files through the `libc` crate, so the `linux`
+//! feature of the generated crate must enable an optional dependency on it:
+//!
+//! ``` toml
+//! [dependencies]
+//! libc = { version = "0.2.18", optional = true }
+//!
+//! [features]
+//! linux = ["libc"]
+//! ```
+//!
+//! With `--output-dir` the crate root declares `libc` when the feature is
+//! enabled; with `--device` the crate that includes the generated code must
+//! declare it.
+//!
+//! The mock and the simulated buses identify the registers by their physical
+//! address so they don't work with mapped register blocks.
 
 #![recursion_limit = "128"]
 
@@ -246,16 +658,24 @@ extern crate svd_parser as svd;
 #[macro_use]
 extern crate quote;
 extern crate syn;
+extern crate xmltree;
+
+mod pretty;
+
+pub use pretty::pretty_print;
 
 use std::borrow::Cow;
-use std::io::Write;
-use std::io;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::panic;
 use std::rc::Rc;
 
 use either::Either;
 use inflections::Inflect;
 use quote::Tokens;
-use svd::{Access, Defaults, Peripheral, Register, RegisterInfo};
+use svd::{Access, Defaults, Device, EnumeratedValues, Peripheral, Register,
+          RegisterArrayInfo, RegisterInfo, Usage};
 use syn::*;
 
 /// Trait that sanitizes name avoiding rust keywords and the like.
@@ -276,505 +696,4646 @@ impl SanitizeName for String {
     }
 }
 
-#[doc(hidden)]
-pub fn gen_peripheral(p: &Peripheral, d: &Defaults) -> Vec<Tokens> {
-    assert!(p.derived_from.is_none(),
-            "DerivedFrom not supported here (should be resolved earlier)");
-
-    let mut items = vec![];
-    let mut fields = vec![];
-    let mut offset = 0;
-    let mut i = 0;
-    let registers = p.registers
-        .as_ref()
-        .expect(&format!("{:#?} has no `registers` field", p));
+/// Error raised while generating code from a SVD file
+///
+/// `path` is the location of the problematic element in the SVD file, e.g.
+/// `GPIOA.MODER.MODER0`.
+#[derive(Debug)]
+pub enum Error {
+    /// The peripheral has no registers
+    NoRegisters { path: String },
+    /// The register has no size and the device doesn't specify a default one
+    NoSize { path: String },
+    /// The register or field is wider than what's supported
+    TooWide { path: String, width: u32 },
+    /// The element is derived from `base`, which doesn't exist
+    DerivedFromNotFound { path: String, base: String },
+    /// The peripheral is derived from another one; `derivedFrom` must be
+    /// resolved before generating its register map
+    UnresolvedDerivedFrom { path: String },
+    /// The element is, directly or indirectly, derived from itself; `cycle`
+    /// lists the elements of the cycle
+    DerivedFromCycle { path: String, cycle: Vec<String> },
+    /// The `dim`, `dimIncrement` or `dimIndex` of the peripheral or cluster is
+    /// invalid, or its name has no `%s` placeholder. The elements of an array
+    /// of clusters must also fit in `dimIncrement` bytes
+    InvalidDim { path: String },
+    /// The `element` of the register, field or interrupt has an invalid
+    /// `value`
+    InvalidValue {
+        path: String,
+        element: String,
+        value: String,
+    },
+    /// The interrupt has the same name as `other` but a different number
+    /// (`value`), or the same number but a different name
+    DuplicateInterrupt {
+        path: String,
+        value: u32,
+        other: String,
+        other_value: u32,
+    },
+    /// The SVD file is not valid XML, or `svd-parser` couldn't parse it
+    InvalidSvd { message: String },
+}
 
-    for register in expand(registers).iter() {
-        let pad = if let Some(pad) = register.offset
-            .checked_sub(offset) {
-            pad
-        } else {
-            writeln!(io::stderr(),
-                     "WARNING {} overlaps with another register at offset \
-                      {}. Ignoring.",
-                     register.name,
-                     register.offset)
-                .ok();
-            continue;
-        };
+/// Result of the code generators
+pub type Result<T> = std::result::Result<T, Error>;
 
-        if pad != 0 {
-            let name = Ident::new(format!("_reserved{}", i));
-            let pad = pad as usize;
-            fields.push(quote! {
-                #name : [u8; #pad]
-            });
-            i += 1;
+impl Error {
+    /// Prefixes the path of the error with the name of the `parent` element
+    fn within(mut self, parent: &str) -> Error {
+        if let Error::InvalidSvd { .. } = self {
+            return self;
         }
 
-        let comment = &format!("0x{:02x} - {}",
-                               register.offset,
-                               respace(&register.info
-                                   .description))[..];
+        {
+            let path = match self {
+                Error::NoRegisters { ref mut path } |
+                Error::NoSize { ref mut path } |
+                Error::TooWide { ref mut path, .. } |
+                Error::DerivedFromNotFound { ref mut path, .. } |
+                Error::UnresolvedDerivedFrom { ref mut path } |
+                Error::DerivedFromCycle { ref mut path, .. } |
+                Error::InvalidDim { ref mut path } |
+                Error::InvalidValue { ref mut path, .. } |
+                Error::DuplicateInterrupt { ref mut path, .. } => path,
+                Error::InvalidSvd { .. } => unreachable!(),
+            };
 
-        let reg_ty = match register.ty {
-            Either::Left(ref ty) => Ident::from(&**ty),
-            Either::Right(ref ty) => Ident::from(&***ty),
-        };
-        let reg_name = Ident::new(&*register.name);
-        fields.push(quote! {
-            #[doc = #comment]
-            pub #reg_name : #reg_ty
-        });
+            *path = if path.is_empty() {
+                parent.to_owned()
+            } else {
+                format!("{}.{}", parent, path)
+            };
+        }
 
-        offset = register.offset +
-                 register.info
-            .size
-            .or(d.size)
-            .expect(&format!("{:#?} has no `size` field", register.info)) /
-                 8;
+        self
     }
+}
 
-    let p_name = Ident::new(p.name.to_pascal_case());
-
-    if let Some(description) = p.description.as_ref() {
-        let comment = &respace(description)[..];
-        items.push(quote! {
-            #[doc = #comment]
-        });
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoRegisters { ref path } => {
+                write!(f, "peripheral `{}` has no registers", path)
+            }
+            Error::NoSize { ref path } => {
+                write!(f,
+                       "register `{}` has no `size` and the device doesn't \
+                        specify a default one",
+                       path)
+            }
+            Error::TooWide { ref path, width } => {
+                write!(f,
+                       "`{}` is {} bits wide but at most 64 bits are \
+                        supported",
+                       path,
+                       width)
+            }
+            Error::DerivedFromNotFound { ref path, ref base } => {
+                write!(f,
+                       "`{}` is derived from `{}`, which doesn't exist",
+                       path,
+                       base)
+            }
+            Error::UnresolvedDerivedFrom { ref path } => {
+                write!(f,
+                       "peripheral `{}` is derived from another peripheral; \
+                        `derivedFrom` must be resolved first",
+                       path)
+            }
+            Error::DerivedFromCycle { ref path, ref cycle } => {
+                write!(f,
+                       "`{}` is derived from itself: {}",
+                       path,
+                       cycle.join(" -> "))
+            }
+            Error::InvalidDim { ref path } => {
+                write!(f,
+                       "`{}` has an invalid `dim`, `dimIncrement` or \
+                        `dimIndex`, or its name has no `%s`",
+                       path)
+            }
+            Error::InvalidValue { ref path, ref element, ref value } => {
+                write!(f,
+                       "`{}` has an invalid `{}`: `{}`",
+                       path,
+                       element,
+                       value)
+            }
+            Error::DuplicateInterrupt { ref path,
+                                        value,
+                                        ref other,
+                                        other_value } => {
+                write!(f,
+                       "interrupt `{}` ({}) conflicts with interrupt `{}` \
+                        ({}); interrupts that share a name must share a \
+                        number, and vice versa",
+                       path,
+                       value,
+                       other,
+                       other_value)
+            }
+            Error::InvalidSvd { ref message } => {
+                write!(f, "invalid SVD file: {}", message)
+            }
+        }
     }
+}
 
-    let struct_ = quote! {
-        #[repr(C)]
-        pub struct #p_name {
-            #(#fields),*
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::NoRegisters { .. } => "peripheral has no registers",
+            Error::NoSize { .. } => "register has no size",
+            Error::TooWide { .. } => "register or field is too wide",
+            Error::DerivedFromNotFound { .. } => "base element not found",
+            Error::UnresolvedDerivedFrom { .. } => "unresolved `derivedFrom`",
+            Error::DerivedFromCycle { .. } => "`derivedFrom` cycle",
+            Error::InvalidDim { .. } => "invalid `dim`",
+            Error::InvalidValue { .. } => "invalid value",
+            Error::DuplicateInterrupt { .. } => "duplicate interrupt",
+            Error::InvalidSvd { .. } => "invalid SVD file",
         }
-    };
-
-    items.push(struct_);
+    }
+}
 
-    for register in registers {
-        let access = access(&register);
+/// Problem with the SVD file that doesn't stop the code generation
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    /// The peripheral has no registers so it's left out of the generated code
+    NoRegisters { path: String },
+}
 
-        items.extend(gen_register(register, d));
-        if let Some(ref fields) = register.fields {
-            if access != Access::WriteOnly {
-                items.extend(gen_register_r(register, d, fields));
-            }
-            if access != Access::ReadOnly {
-                items.extend(gen_register_w(register, d, fields));
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::NoRegisters { ref path } => {
+                write!(f, "{} has no `registers` field. Ignoring.", path)
             }
         }
     }
-
-    items
 }
 
-struct ExpandedRegister<'a> {
-    info: &'a RegisterInfo,
-    name: String,
-    offset: u32,
-    ty: Either<String, Rc<String>>,
+/// Parses the SVD file `xml`
+///
+/// Returns the device plus the `Options` that carry the parts of the file that
+/// `svd-parser` doesn't parse, like the `peripheral_extras`. The registers and
+/// fields that are derived from other registers and fields are resolved
+/// before `svd-parser` sees them. Unlike `svd::parse`, which panics, this
+/// function reports the files that are not valid XML, or that `svd-parser`
+/// can't parse, as errors.
+pub fn parse(xml: &str) -> Result<(Device, Options)> {
+    let mut tree = try!(parse_xml(xml));
+    let device = if try!(resolve_elements(&mut tree)) {
+        let mut resolved = vec![];
+        tree.write(&mut resolved);
+        try!(parse_device(&String::from_utf8_lossy(&resolved)))
+    } else {
+        try!(parse_device(xml))
+    };
+
+    let mut opts = Options::default();
+    opts.peripheral_extras = try!(extras(&tree));
+    opts.peripheral_interrupts = try!(peripheral_interrupts(&tree));
+    opts.peripheral_arrays = try!(arrays(&tree));
+
+    Ok((device, opts))
 }
 
-/// Takes a list of "registers", some of which may actually be register arrays,
-/// and turns it into a new *sorted* (by address offset) list of registers where
-/// the register arrays have been expanded.
-fn expand(registers: &[Register]) -> Vec<ExpandedRegister> {
-    let mut out = vec![];
+/// Parses `xml` into a tree of XML elements
+fn parse_xml(xml: &str) -> Result<xmltree::Element> {
+    xmltree::Element::parse(xml.as_bytes())
+        .map_err(|e| Error::InvalidSvd { message: e.to_string() })
+}
 
-    for r in registers {
-        match *r {
-            Register::Single(ref info) => {
-                out.push(ExpandedRegister {
-                    info: info,
-                    name: info.name.to_snake_case().sanitize(),
-                    offset: info.address_offset,
-                    ty: Either::Left(info.name.to_pascal_case()),
-                })
-            }
-            Register::Array(ref info, ref array_info) => {
-                let has_brackets = info.name.contains("[%s]");
+/// Parses the SVD file `xml` using `svd-parser`, whose panics become errors
+///
+/// `svd-parser` panics on the `resetValue`s and `resetMask`s that don't fit in
+/// 32 bits so these are cut down to their low 32 bits first; `Extras` has
+/// their whole values. The panic hook is silenced while `svd-parser` runs,
+/// and only then, so the messages of its panics, which become errors, aren't
+/// printed.
+fn parse_device(xml: &str) -> Result<Device> {
+    let mut tree = try!(parse_xml(xml));
+    narrow(&mut tree);
+    let mut xml = vec![];
+    tree.write(&mut xml);
+    let xml = String::from_utf8_lossy(&xml).into_owned();
 
-                let ty = if has_brackets {
-                    info.name.replace("[%s]", "")
-                } else {
-                    info.name.replace("%s", "")
-                };
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let device = panic::catch_unwind(|| svd::parse(&xml));
+    panic::set_hook(hook);
 
-                let ty = Rc::new(ty.to_pascal_case());
+    device.map_err(|e| {
+        let message = e.downcast_ref::<String>()
+            .map(|s| &s[..])
+            .or_else(|| e.downcast_ref::<&str>().map(|s| *s))
+            .unwrap_or("unknown error");
 
-                let indices = array_info.dim_index
-                    .as_ref()
-                    .map(|v| Cow::from(&**v))
-                    .unwrap_or_else(|| {
-                        Cow::from((0..array_info.dim)
-                            .map(|i| i.to_string())
-                            .collect::<Vec<_>>())
-                    });
+        Error::InvalidSvd {
+            message: format!("`svd-parser` failed at {}", message),
+        }
+    })
+}
 
-                for (idx, i) in indices.iter().zip(0..) {
-                    let name = if has_brackets {
-                        info.name.replace("[%s]", idx)
-                    } else {
-                        info.name.replace("%s", idx)
-                    };
+/// Cuts the `resetValue`s and `resetMask`s of `e`, and of its descendants,
+/// down to their low 32 bits
+fn narrow(e: &mut xmltree::Element) {
+    if e.name == "resetValue" || e.name == "resetMask" {
+        if let Some(n) = e.text.as_ref().and_then(|t| parse_u64(t.trim())) {
+            e.text = Some(format!("0x{:08x}", n as u32));
+        }
+    }
 
-                    let offset = info.address_offset +
-                                 i * array_info.dim_increment;
+    for c in &mut e.children {
+        narrow(c);
+    }
+}
 
-                    out.push(ExpandedRegister {
-                        info: info,
-                        name: name.to_snake_case().sanitize(),
-                        offset: offset,
-                        ty: Either::Right(ty.clone()),
-                    });
+/// Resolves the `derivedFrom` attributes of the registers and fields of the
+/// SVD file `tree`, which `svd-parser` doesn't parse
+///
+/// A derived register, or field, takes the elements that it doesn't specify
+/// from its base. The base is either a sibling of the derived element or the
+/// path to an element of another peripheral or cluster, e.g. `GPIOA.MODER` or
+/// `GPIOA.MODER.MODER0`. Returns whether any element was derived.
+fn resolve_elements(tree: &mut xmltree::Element) -> Result<bool> {
+    let mut derived = false;
+    // NOTE registers go first so that their fields are in place when the
+    // fields are resolved
+    for kind in &["register", "field"] {
+        let snapshot = tree.clone();
+        if let Some(ps) = tree.get_mut_child("peripherals") {
+            for p in &mut ps.children {
+                let name = child_text(p, "name").unwrap_or("").to_owned();
+                if let Some(block) = p.get_mut_child("registers") {
+                    derived |=
+                        try!(resolve_block(&snapshot, block, &name, kind));
                 }
             }
         }
     }
 
-    out.sort_by_key(|x| x.offset);
-
-    out
+    Ok(derived)
 }
 
-fn type_of(r: &Register) -> String {
-    let ty = match *r {
-        Register::Single(ref info) => Cow::from(&*info.name),
-        Register::Array(ref info, _) => {
-            if info.name.contains("[%s]") {
-                info.name.replace("[%s]", "").into()
-            } else {
-                info.name.replace("%s", "").into()
+/// Resolves the derived elements of `kind` in `block`, the registers of a
+/// peripheral or a cluster, whose path is `path`
+fn resolve_block(snapshot: &xmltree::Element,
+                 block: &mut xmltree::Element,
+                 path: &str,
+                 kind: &str)
+                 -> Result<bool> {
+    let mut derived = false;
+    let siblings = block.clone();
+    for e in &mut block.children {
+        let path = format!("{}.{}", path, child_text(e, "name").unwrap_or(""));
+        match &e.name[..] {
+            "cluster" => {
+                derived |= try!(resolve_block(snapshot, e, &path, kind));
+            }
+            "register" if kind == "register" => {
+                if e.attributes.contains_key("derivedFrom") {
+                    *e = try!(resolve_element(snapshot,
+                                              &siblings,
+                                              e,
+                                              &path,
+                                              &mut vec![]));
+                    derived = true;
+                }
+            }
+            "register" => {
+                if let Some(fields) = e.get_mut_child("fields") {
+                    let siblings = fields.clone();
+                    for f in &mut fields.children {
+                        if f.attributes.contains_key("derivedFrom") {
+                            let path = format!("{}.{}",
+                                               path,
+                                               child_text(f, "name")
+                                                   .unwrap_or(""));
+                            *f = try!(resolve_element(snapshot,
+                                                      &siblings,
+                                                      f,
+                                                      &path,
+                                                      &mut vec![]));
+                            derived = true;
+                        }
+                    }
+                }
             }
+            _ => {}
         }
-    };
+    }
 
-    (&*ty).to_pascal_case()
+    Ok(derived)
 }
 
-fn access(r: &Register) -> Access {
-    r.access.unwrap_or_else(|| {
-        if let Some(ref fields) = r.fields {
-            if fields.iter().all(|f| f.access == Some(Access::ReadOnly)) {
-                Access::ReadOnly
-            } else if fields.iter().all(|f| f.access == Some(Access::WriteOnly)) {
-                Access::WriteOnly
-            } else {
-                Access::ReadWrite
-            }
-        } else {
-            Access::ReadWrite
+/// Resolves the register, or field, `e` whose path is `path`. `siblings` is
+/// the element that contains `e`.
+fn resolve_element(snapshot: &xmltree::Element,
+                   siblings: &xmltree::Element,
+                   e: &xmltree::Element,
+                   path: &str,
+                   chain: &mut Vec<String>)
+                   -> Result<xmltree::Element> {
+    let bn = match e.attributes.get("derivedFrom") {
+        Some(bn) => bn,
+        None => return Ok(e.clone()),
+    };
+
+    let lpath = path.to_lowercase();
+    chain.push(path.to_owned());
+    if chain[..chain.len() - 1].iter().any(|p| p.to_lowercase() == lpath) {
+        return Err(Error::DerivedFromCycle {
+            path: chain[0].clone(),
+            cycle: chain.clone(),
+        });
+    }
+
+    let not_found = || {
+        Error::DerivedFromNotFound {
+            path: path.to_owned(),
+            base: bn.clone(),
         }
-    })
+    };
+    let (bsiblings, be, bpath) = if bn.contains('.') {
+        let (bsiblings, be) =
+            try!(find_element(snapshot, bn, &e.name).ok_or_else(not_found));
+        (bsiblings, be, bn.clone())
+    } else {
+        let be = try!(find_child(siblings, &e.name, bn).ok_or_else(not_found));
+        let parent = path.rsplitn(2, '.').nth(1).unwrap_or("");
+        (siblings, be, format!("{}.{}", parent, bn))
+    };
+    let be = try!(resolve_element(snapshot, bsiblings, be, &bpath, chain));
+
+    let mut e = e.clone();
+    e.attributes.remove("derivedFrom");
+    for child in &be.children {
+        if e.get_child(&child.name[..]).is_none() {
+            e.children.push(child.clone());
+        }
+    }
+
+    Ok(e)
 }
 
-#[doc(hidden)]
-pub fn gen_register(r: &Register, d: &Defaults) -> Vec<Tokens> {
-    let mut items = vec![];
+/// Finds the register, or field, `path`, e.g. `GPIOA.MODER.MODER0`, and the
+/// element that contains it
+fn find_element<'a>(snapshot: &'a xmltree::Element,
+                    path: &str,
+                    kind: &str)
+                    -> Option<(&'a xmltree::Element, &'a xmltree::Element)> {
+    let names = path.split('.').collect::<Vec<_>>();
+    // index of the register
+    let r = if kind == "field" { 2 } else { 1 };
+    if names.len() <= r {
+        return None;
+    }
+    let r = names.len() - r;
 
-    let ty = type_of(r);
-    let name = Ident::new(&*ty);
-    let bits_ty = r.size
-        .or(d.size)
-        .expect(&format!("{:#?} has no `size` field", r))
-        .to_ty();
-    let access = access(r);
+    let mut block = match snapshot.get_child("peripherals")
+        .and_then(|ps| find_child(ps, "peripheral", names[0]))
+        .and_then(|p| p.get_child("registers")) {
+        Some(block) => block,
+        None => return None,
+    };
+    for name in &names[1..r] {
+        block = match find_child(block, "cluster", name) {
+            Some(cluster) => cluster,
+            None => return None,
+        };
+    }
 
-    match access {
-        Access::ReadOnly => {
-            items.push(quote! {
-                #[repr(C)]
-                pub struct #name {
-                    register: ::volatile_register::RO<#bits_ty>
-                }
-            });
-        }
-        Access::ReadWrite => {
-            items.push(quote! {
-                #[repr(C)]
-                pub struct #name {
-                    register: ::volatile_register::RW<#bits_ty>
-                }
-            });
-        }
-        Access::WriteOnly => {
-            items.push(quote! {
-                #[repr(C)]
-                pub struct #name {
-                    register: ::volatile_register::WO<#bits_ty>
-                }
-            });
-        }
-        _ => unreachable!(),
+    let register = find_child(block, "register", names[r]);
+    if kind == "field" {
+        register.and_then(|r| r.get_child("fields"))
+            .and_then(|fs| {
+                find_child(fs, "field", names[r + 1]).map(|f| (fs, f))
+            })
+    } else {
+        register.map(|r| (block, r))
     }
+}
 
-    if r.fields.is_some() {
-        let name_r = Ident::new(format!("{}R", ty));
-        let name_w = Ident::new(format!("{}W", ty));
-        match access {
-            Access::ReadOnly => {
-                items.push(quote! {
-                    impl #name {
-                        pub fn read_bits(&self) -> #bits_ty {
-                            self.register.read()
-                        }
+/// Finds the child of `e` of `kind` named `name`. Comparison is case
+/// insensitive.
+fn find_child<'a>(e: &'a xmltree::Element,
+                  kind: &str,
+                  name: &str)
+                  -> Option<&'a xmltree::Element> {
+    let name = name.to_lowercase();
+    e.children.iter().find(|c| {
+        c.name == kind &&
+        child_text(c, "name").map(|n| n.to_lowercase()) == Some(name.clone())
+    })
+}
 
-                        pub fn read(&self) -> #name_r {
-                            #name_r { bits: self.register.read() }
-                        }
-                    }
-                });
-            }
-            Access::ReadWrite => {
-                items.push(quote! {
-                    impl #name {
-                        pub fn read_bits(&self) -> #bits_ty {
-                            self.register.read()
-                        }
+/// Generates the register maps of all the peripherals of a device
+///
+/// Each peripheral gets its own module, which contains its register block
+/// and the types of its registers, plus a handle that owns the register
+/// block. `derivedFrom` peripherals are resolved against the peripheral they
+/// derive from. Peripherals that have the same registers share their register
+/// block type. Peripherals that have no registers are left out; `warnings`
+/// lists them.
+pub fn gen_device(d: &Device) -> Result<Tokens> {
+    gen_device_with(d, &Options::default())
+}
 
-                        pub unsafe fn modify_bits<F>(&mut self, f: F)
-                            where F: FnOnce(&mut #bits_ty)
-                        {
-                            let mut bits = self.register.read();
-                            f(&mut bits);
-                            self.register.write(bits);
-                        }
+/// Options that control the code generated for a device
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Generate a single generic register type, `Reg<SPEC>`, plus one zero
+    /// sized specification type per register, instead of one struct with its
+    /// own `read` / `modify` / `write` methods per register
+    pub generic: bool,
+    /// Parts of the register blocks that `svd-parser` doesn't parse, like the
+    /// register clusters, by peripheral; see `peripheral_extras`
+    pub peripheral_extras: BTreeMap<String, Extras>,
+    /// All the interrupts of each peripheral, by peripheral; `svd-parser`
+    /// only keeps the first one. The peripherals that aren't listed use the
+    /// interrupt that `svd-parser` kept
+    pub peripheral_interrupts: BTreeMap<String, Vec<svd::Interrupt>>,
+    /// Peripherals that have several instances; see `peripheral_arrays`
+    pub peripheral_arrays: Vec<PeripheralArray>,
+    /// Store the registers in `VolatileCell`s so all their methods, including
+    /// `write` and `modify`, take `&self`. The register blocks are not `Sync`
+    pub shared: bool,
+    /// Store the registers in `VolatileCell`s, which the crate root defines,
+    /// instead of the types of the `volatile_register` crate
+    pub self_contained: bool,
+    /// Path of the crate, or module, that provides the `RO`, `RW` and `WO`
+    /// register types; defaults to `::volatile_register`. A path that doesn't
+    /// start with `::` is relative to the crate root
+    pub volatile_path: Option<String>,
+    /// Read and write the registers through the `RegisterBus` trait. The
+    /// `mock` and `sim` features of the generated crate replace the volatile
+    /// accesses with an in-memory mock, or a simulation of the peripherals
+    pub bus: bool,
+    /// Generate, behind the `linux` feature of the generated crate, the
+    /// `linux` module, which maps register blocks from files like `/dev/mem`
+    /// into memory
+    pub linux: bool,
+}
 
-                        pub unsafe fn write_bits(&mut self, bits: #bits_ty) {
-                            self.register.write(bits);
-                        }
+impl Options {
+    /// Whether the crate root defines `VolatileCell`
+    fn volatile_cell(&self) -> bool {
+        self.shared || self.self_contained || self.bus
+    }
 
-                        pub fn modify<F>(&mut self, f: F)
-                            where for<'w> F: FnOnce(&#name_r, &'w mut #name_w) -> &'w mut #name_w,
-                        {
-                            let bits = self.register.read();
-                            let r = #name_r { bits: bits };
-                            let mut w = #name_w { bits: bits };
-                            f(&r, &mut w);
-                            self.register.write(w.bits);
-                        }
+    /// Returns the path of the crate that provides the register types, unless
+    /// the crate root defines them
+    fn volatile_path(&self) -> Option<&str> {
+        if self.volatile_cell() {
+            None
+        } else {
+            Some(self.volatile_path
+                .as_ref()
+                .map(|p| &p[..])
+                .unwrap_or("::volatile_register"))
+        }
+    }
+}
 
-                        pub fn read(&self) -> #name_r {
-                            #name_r { bits: self.register.read() }
-                        }
+/// Like `gen_device` but with the given options
+pub fn gen_device_with(d: &Device, opts: &Options) -> Result<Tokens> {
+    gen_device_(d, opts, true).map(|(root, _)| root)
+}
 
-                        pub fn write<F>(&mut self, f: F)
-                            where F: FnOnce(&mut #name_w) -> &mut #name_w,
-                        {
-                            let mut w = #name_w::reset_value();
-                            f(&mut w);
-                            self.register.write(w.bits);
-                        }
-                    }
-                });
-            }
+/// Generates the register maps of all the peripherals of a device as a set of
+/// files
+///
+/// Returns the name and the contents of each file: `lib.rs`, the crate root,
+/// plus one file per peripheral named after the module of the peripheral,
+/// e.g. `gpioa.rs`. The crate root is the output of `gen_device`, where the
+/// modules of the peripherals are declared rather than inlined, preceded by
+/// the `no_std` attribute and the crate that provides the register types,
+/// `volatile_register` by default.
+pub fn gen_device_files(d: &Device) -> Result<Vec<(String, Tokens)>> {
+    gen_device_files_with(d, &Options::default())
+}
 
-            Access::WriteOnly => {
-                items.push(quote! {
-                    impl #name {
-                        pub unsafe fn write_bits(&mut self, bits: #bits_ty) {
-                            self.register.write(bits);
-                        }
+/// Like `gen_device_files` but with the given options
+pub fn gen_device_files_with(d: &Device,
+                             opts: &Options)
+                             -> Result<Vec<(String, Tokens)>> {
+    let (root, modules) = try!(gen_device_(d, opts, false));
 
-                        pub fn write<F>(&self, f: F)
-                            where F: FnOnce(&mut #name_w) -> &mut #name_w,
-                        {
-                            let mut w = #name_w::reset_value();
-                            f(&mut w);
-                            self.register.write(w.bits);
-                        }
-                    }
-                });
+    // NOTE a relative `volatile_path` refers to a module of the crate
+    let krate = opts.volatile_path()
+        .and_then(|p| if p.starts_with("::") { Some(&p[2..]) } else { None })
+        .and_then(|p| p.split("::").next())
+        .map(|krate| {
+            let krate = Ident::new(krate);
+            quote! {
+                extern crate #krate;
             }
+        });
 
-            _ => unreachable!(),
+    // NOTE the mock and simulated buses use `std` to keep their state, and
+    // the `linux` module to map files; the generated code still refers to
+    // `core`
+    let mut std_features = vec![];
+    if opts.bus {
+        std_features.push("mock");
+        std_features.push("sim");
+    }
+    if opts.linux {
+        std_features.push("linux");
+    }
+    let no_std = if std_features.is_empty() {
+        quote! { #![no_std] }
+    } else {
+        let std_features = std_features.iter()
+            .map(|f| quote! { feature = #f })
+            .collect::<Vec<_>>();
+        let std_features = quote! { any(#(#std_features),*) };
+        quote! {
+            #![cfg_attr(not(#std_features), no_std)]
+
+            #[cfg(#std_features)]
+            extern crate core;
+        }
+    };
+
+    // NOTE the `linux` module maps files through `libc`, an optional
+    // dependency that the `linux` feature enables
+    let libc = if opts.linux {
+        quote! {
+            #[cfg(feature = "linux")]
+            extern crate libc;
         }
     } else {
-        match access {
-            Access::ReadOnly => {
-                items.push(quote! {
-                    impl #name {
-                        pub fn read(&self) -> #bits_ty {
-                            self.register.read()
-                        }
-                    }
-                });
-            }
-            Access::ReadWrite => {
-                items.push(quote! {
-                    impl #name {
-                        pub fn read(&self) -> #bits_ty {
-                            self.register.read()
-                        }
+        quote! {}
+    };
 
-                        pub fn write(&mut self, value: #bits_ty) {
-                            self.register.write(value);
-                        }
-                    }
-                });
-            }
+    let root = quote! {
+        #no_std
 
-            Access::WriteOnly => {
-                items.push(quote! {
-                    impl #name {
-                        pub fn write(&mut self, value: #bits_ty) {
-                            self.register.write(value);
-                        }
-                    }
-                });
-            }
+        #krate
 
-            _ => unreachable!(),
-        }
-    }
+        #libc
 
-    items
+        #root
+    };
+
+    let mut files = vec![(String::from("lib.rs"), root)];
+    files.extend(modules.into_iter()
+        .map(|(name, module)| (format!("{}.rs", name), module)));
+
+    Ok(files)
 }
 
-#[doc(hidden)]
-pub fn gen_register_r(r: &Register,
-                      d: &Defaults,
-                      fields: &[svd::Field])
-                      -> Vec<Tokens> {
+/// Generates the crate root of the device plus the module of each peripheral
+///
+/// If `inline` is `true` the modules are part of the crate root; otherwise
+/// the crate root only declares them.
+fn gen_device_(d: &Device,
+               opts: &Options,
+               inline: bool)
+               -> Result<(Tokens, Vec<(String, Tokens)>)> {
     let mut items = vec![];
+    let mut handles = vec![];
+    let mut modules = vec![];
+    // peripherals that define a register block type, with their extras
+    let mut blocks: Vec<(&Peripheral, &Extras)> = vec![];
 
-    let name = Ident::new(format!("{}R", type_of(r)));
-    let bits_ty = r.size
-        .or(d.size)
-        .expect(&format!("{:#?} has no `size` field", r))
-        .to_ty();
+    if opts.bus {
+        items.push(gen_bus());
+    }
 
-    items.push(quote! {
-        #[derive(Clone, Copy)]
-        #[repr(C)]
-        pub struct #name {
-            bits: #bits_ty,
-        }});
+    if opts.linux {
+        items.push(gen_linux());
+    }
 
-    let mut impl_items = vec![];
+    if opts.volatile_cell() {
+        items.push(gen_volatile_cell(opts));
+    }
 
-    for field in fields {
-        // Skip fields named RESERVED because, well, they are reserved so they
-        // shouldn't be modified/exposed
-        if field.name.to_lowercase() == "reserved" {
-            continue;
-        }
+    if opts.generic {
+        items.push(gen_generic(opts));
+    }
 
-        if let Some(Access::WriteOnly) = field.access {
+    let extras = try!(resolve_extras(d, opts));
+    let none = Extras::default();
+    let peripherals = try!(peripherals(d, &opts.peripheral_arrays));
+    for p in &peripherals {
+        if p.registers.is_none() {
             continue;
         }
 
-        let name = Ident::new(field.name.to_snake_case().sanitize());
-        let offset = field.bit_range.offset as u8;
-
-        let width = field.bit_range.width;
+        let p_extras = extras.get(&p.name).unwrap_or(&none);
+        let name_sc = p.name.to_snake_case().sanitize();
+        let name_sc_ = Ident::new(&*name_sc);
 
-        if let Some(description) = field.description.as_ref() {
-            let bits = if width == 1 {
-                format!("Bit {}", field.bit_range.offset)
-            } else {
-                format!("Bits {}:{}",
-                        field.bit_range.offset,
-                        field.bit_range.offset + width - 1)
-            };
+        items.push(gen_handle(p, opts));
+        if opts.linux {
+            items.push(gen_handle_map(p));
+        }
+        handles.push(p.name.clone());
 
-            let comment = &format!("{} - {}", bits, respace(description))[..];
-            impl_items.push(quote! {
+        if let Some(description) = p.description.as_ref() {
+            let comment = &respace(description)[..];
+            items.push(quote! {
                 #[doc = #comment]
             });
         }
 
-        let item = if width == 1 {
-            quote! {
-                pub fn #name(&self) -> bool {
-                    const OFFSET: u8 = #offset;
+        let base = blocks.iter()
+            .find(|&&(bp, bp_extras)| same_block(p, p_extras, bp, bp_extras))
+            .map(|&(bp, _)| bp.name.clone());
+        let p_items = if let Some(base) = base {
+            // Reuse the register block type of the peripheral that has the
+            // same registers
+            let name_pc = Ident::new(p.name.to_pascal_case());
+            let base_pc = Ident::new(base.to_pascal_case());
+            let base_sc = Ident::new(base.to_snake_case().sanitize());
+            let comment = &format!("Register block of {}; same type as \
+                                    `{}::{}`",
+                                   p.name,
+                                   base_sc,
+                                   base_pc)[..];
+            vec![quote! {
+                pub use super::#base_sc::*;
 
-                    self.bits & (1 << OFFSET) != 0
-                }
-            }
+                #[doc = #comment]
+                pub type #name_pc = super::#base_sc::#base_pc;
+            }]
         } else {
-            let width_ty = width.to_ty();
-            let mask: u64 = (1 << width) - 1;
-            let mask = Lit::Int(mask, IntTy::Unsuffixed);
-
-            quote! {
-                pub fn #name(&self) -> #width_ty {
-                    const MASK: #bits_ty = #mask;
-                    const OFFSET: u8 = #offset;
+            blocks.push((p, p_extras));
+            try!(gen_peripheral_(p, &d.defaults, opts, p_extras)
+                .map_err(|e| e.within(&p.name)))
+        };
 
-                    ((self.bits >> OFFSET) & MASK) as #width_ty
+        if inline {
+            items.push(quote! {
+                pub mod #name_sc_ {
+                    #(#p_items)*
                 }
-            }
-        };
+            });
+        } else {
+            items.push(quote! {
+                pub mod #name_sc_;
+            });
+            modules.push((name_sc,
+                          quote! {
+                #(#p_items)*
+            }));
+        }
+    }
 
-        impl_items.push(item);
+    items.push(gen_peripherals(&handles));
+    if opts.bus {
+        items.push(gen_sim(&peripherals));
+    }
+    for array in &opts.peripheral_arrays {
+        items.extend(gen_peripheral_array(array, &peripherals, opts));
     }
+    items.push(try!(gen_interrupts(d, opts)));
 
-    items.push(quote! {
-        impl #name {
-            #(#impl_items)*
-        }
-    });
+    Ok((quote! {
+            #(#items)*
+        },
+        modules))
+}
 
-    items
+/// Returns the problems with the device `d` that don't stop the code
+/// generation, like the peripherals that `gen_device` leaves out
+pub fn warnings(d: &Device) -> Result<Vec<Warning>> {
+    Ok(try!(peripherals(d, &[]))
+        .into_iter()
+        .filter(|p| p.registers.is_none())
+        .map(|p| Warning::NoRegisters { path: p.name })
+        .collect())
 }
 
-#[doc(hidden)]
-pub fn gen_register_w(r: &Register,
-                      d: &Defaults,
-                      fields: &[svd::Field])
-                      -> Vec<Tokens> {
-    let mut items = vec![];
+/// Returns the peripherals of the device with the peripheral `arrays`
+/// expanded and their `derivedFrom` resolved
+fn peripherals(d: &Device,
+               arrays: &[PeripheralArray])
+               -> Result<Vec<Peripheral>> {
+    Ok(try!(resolve(&try!(expand_peripheral_arrays(d, arrays)))).peripherals)
+}
 
-    let name = Ident::new(format!("{}W", type_of(r)));
-    let bits_ty = r.size
-        .or(d.size)
-        .expect(&format!("{:#?} has no `size` field", r))
-        .to_ty();
-    items.push(quote! {
-        #[derive(Clone, Copy)]
-        #[repr(C)]
-        pub struct #name {
-            bits: #bits_ty,
-        }
-    });
+/// Whether the peripherals `a` and `b`, whose extras are given, have the same
+/// register block, regardless of the order in which their registers are
+/// listed
+///
+/// Peripherals that have the same register block, e.g. the ones that derive
+/// from another peripheral, share their register block type.
+fn same_block(a: &Peripheral,
+              a_extras: &Extras,
+              b: &Peripheral,
+              b_extras: &Extras)
+              -> bool {
+    let none = vec![];
+    same_registers(a.registers.as_ref().unwrap_or(&none),
+                   b.registers.as_ref().unwrap_or(&none)) &&
+    same_extras(a_extras, b_extras)
+}
 
-    let mut impl_items = vec![];
+fn same_extras(a: &Extras, b: &Extras) -> bool {
+    a.resets == b.resets && a.semantics == b.semantics &&
+    a.clusters.len() == b.clusters.len() &&
+    a.clusters.iter().zip(&b.clusters).all(|(a, b)| {
+        a.name == b.name && a.address_offset == b.address_offset &&
+        same_array_info(a.array_info.as_ref(), b.array_info.as_ref()) &&
+        same_registers(&a.registers, &b.registers) &&
+        same_extras(&a.extras, &b.extras)
+    })
+}
 
-    if let Some(reset_value) =
-        r.reset_value
-            .or(d.reset_value)
-            .map(|x| Lit::Int(x as u64, IntTy::Unsuffixed)) {
-        impl_items.push(quote! {
-            /// Reset value
-            pub fn reset_value() -> Self {
-                #name { bits: #reset_value }
-            }
+fn same_registers(a: &[Register], b: &[Register]) -> bool {
+    fn sorted(registers: &[Register]) -> Vec<&Register> {
+        let mut registers = registers.iter().collect::<Vec<_>>();
+        registers.sort_by(|a, b| {
+            (a.address_offset, &a.name).cmp(&(b.address_offset, &b.name))
         });
+        registers
     }
 
-    for field in fields {
-        // Skip fields named RESERVED. See `gen_register_r` for an explanation
-        if field.name.to_lowercase() == "reserved" {
-            continue;
+    a.len() == b.len() &&
+    sorted(a).into_iter().zip(sorted(b)).all(|(a, b)| same_register(a, b))
+}
+
+fn same_register(a: &Register, b: &Register) -> bool {
+    let array_info = |r: &Register| match *r {
+        Register::Single(_) => None,
+        Register::Array(_, ref array_info) => Some(array_info.clone()),
+    };
+    let none = vec![];
+    let (a_fields, b_fields) = (a.fields.as_ref().unwrap_or(&none),
+                                b.fields.as_ref().unwrap_or(&none));
+
+    a.name == b.name && a.address_offset == b.address_offset &&
+    a.size == b.size && a.access == b.access &&
+    a.reset_value == b.reset_value && a.reset_mask == b.reset_mask &&
+    same_array_info(array_info(a).as_ref(), array_info(b).as_ref()) &&
+    a_fields.len() == b_fields.len() &&
+    a_fields.iter().zip(b_fields).all(|(a, b)| same_field(a, b))
+}
+
+fn same_array_info(a: Option<&RegisterArrayInfo>,
+                   b: Option<&RegisterArrayInfo>)
+                   -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => {
+            a.dim == b.dim && a.dim_increment == b.dim_increment &&
+            a.dim_index == b.dim_index
         }
+        _ => false,
+    }
+}
 
-        if let Some(Access::ReadOnly) = field.access {
-            continue;
+fn same_field(a: &svd::Field, b: &svd::Field) -> bool {
+    a.name == b.name && a.bit_range.offset == b.bit_range.offset &&
+    a.bit_range.width == b.bit_range.width && a.access == b.access &&
+    match (a.enumerated_values.as_ref(), b.enumerated_values.as_ref()) {
+        (None, None) => true,
+        (Some(a), Some(b)) => {
+            a.name == b.name && a.derived_from == b.derived_from &&
+            same_usage(a.usage, b.usage) &&
+            a.values.len() == b.values.len() &&
+            a.values.iter().zip(&b.values).all(|(a, b)| {
+                a.name == b.name && a.value == b.value &&
+                a.is_default == b.is_default
+            })
         }
+        _ => false,
+    }
+}
 
-        let name = Ident::new(field.name.to_snake_case().sanitize());
-        let offset = field.bit_range.offset as u8;
+fn same_usage(a: Option<Usage>, b: Option<Usage>) -> bool {
+    match (a, b) {
+        (None, None) |
+        (Some(Usage::Read), Some(Usage::Read)) |
+        (Some(Usage::Write), Some(Usage::Write)) |
+        (Some(Usage::ReadWrite), Some(Usage::ReadWrite)) => true,
+        _ => false,
+    }
+}
 
-        let width = field.bit_range.width;
+/// A peripheral that has `dim` instances, `dim_increment` bytes apart
+#[derive(Clone, Debug)]
+pub struct PeripheralArray {
+    /// Name of the peripheral, e.g. `UART%s`
+    pub name: String,
+    pub info: RegisterArrayInfo,
+}
 
-        if let Some(description) = field.description.as_ref() {
-            let bits = if width == 1 {
-                format!("Bit {}", field.bit_range.offset)
-            } else {
-                format!("Bits {}:{}",
+impl PeripheralArray {
+    /// Returns the indices of the instances, e.g. `0`, `1`, ...
+    fn indices(&self) -> Vec<String> {
+        self.info
+            .dim_index
+            .clone()
+            .unwrap_or_else(|| {
+                (0..self.info.dim).map(|i| i.to_string()).collect()
+            })
+    }
+
+    /// Returns the name of the instance that has the given `index`, e.g.
+    /// `UART0`
+    fn instance(&self, index: &str) -> String {
+        self.name.replace("[%s]", index).replace("%s", index)
+    }
+}
+
+/// Returns the peripherals of the SVD file `xml` that have several instances
+///
+/// **NOTE** `svd-parser` doesn't parse the `dim` elements of peripherals so
+/// this function extracts them from the SVD file.
+pub fn peripheral_arrays(xml: &str) -> Result<Vec<PeripheralArray>> {
+    arrays(&try!(parse_xml(xml)))
+}
+
+/// Returns the peripherals of the SVD file `tree` that have several instances
+fn arrays(tree: &xmltree::Element) -> Result<Vec<PeripheralArray>> {
+    let mut arrays = vec![];
+    for p in tree.get_child("peripherals")
+        .iter()
+        .flat_map(|ps| ps.children.iter()) {
+        let name = child_text(p, "name").unwrap_or("");
+        if let Some(info) = try!(array_info(p).map_err(|e| e.within(name))) {
+            arrays.push(PeripheralArray {
+                name: name.to_owned(),
+                info: info,
+            });
+        }
+    }
+
+    Ok(arrays)
+}
+
+/// Returns the name of the peripheral array that `name` is an instance of, or
+/// `name` itself if it's not an instance of any of the `arrays`
+fn array_of<'a>(arrays: &'a [PeripheralArray], name: &'a str) -> &'a str {
+    arrays.iter()
+        .find(|a| a.indices().iter().any(|i| a.instance(i) == name))
+        .map(|a| &a.name[..])
+        .unwrap_or(name)
+}
+
+/// Replaces each of the peripheral `arrays` of the device with its instances
+///
+/// The instances are copies of the peripheral where `%s` is replaced with
+/// the index of the instance; the base address of the `n`-th instance is `n *
+/// dim_increment` bytes past the base address of the peripheral.
+pub fn expand_peripheral_arrays(d: &Device,
+                                arrays: &[PeripheralArray])
+                                -> Result<Device> {
+    let mut peripherals = d.peripherals.clone();
+
+    for array in arrays {
+        let i = try!(peripherals.iter()
+            .position(|p| p.name == array.name)
+            .ok_or_else(|| Error::InvalidDim { path: array.name.clone() }));
+        let p = peripherals.remove(i);
+
+        for (n, index) in array.indices().iter().enumerate() {
+            let mut instance = p.clone();
+            instance.name = array.instance(index);
+            instance.base_address = p.base_address +
+                                    n as u32 * array.info.dim_increment;
+            instance.description = p.description
+                .as_ref()
+                .map(|d| d.replace("%s", index));
+            peripherals.insert(i + n, instance);
+        }
+    }
+
+    Ok(Device {
+        name: d.name.clone(),
+        peripherals: peripherals,
+        defaults: d.defaults,
+    })
+}
+
+/// Generates a function that returns the register block of the `n`-th
+/// instance of the peripheral `array`
+fn gen_peripheral_array(array: &PeripheralArray,
+                        peripherals: &[Peripheral],
+                        opts: &Options)
+                        -> Option<Tokens> {
+    // NOTE the instances have no register block if they have no registers
+    let first = match array.indices()
+        .first()
+        .map(|i| array.instance(i))
+        .and_then(|name| peripherals.iter().find(|p| p.name == name)) {
+        Some(first) if first.registers.is_some() => first,
+        _ => return None,
+    };
+
+    let name = Ident::new(array.name
+        .replace("[%s]", "")
+        .replace("%s", "")
+        .to_snake_case()
+        .sanitize());
+    let name_pc = Ident::new(first.name.to_pascal_case());
+    let name_sc = Ident::new(first.name.to_snake_case().sanitize());
+    let address = hex(first.base_address);
+    let dim = array.info.dim as usize;
+    let increment = array.info.dim_increment as usize;
+    let comment = &format!("Returns the register block of the `n`-th \
+                            instance of `{}` (`n` in `0..{}`, stride \
+                            0x{:02x}), or `None` if `n` is out of range",
+                           array.name,
+                           dim,
+                           increment)[..];
+    let ptr = if opts.bus {
+        quote! { register_block(#address + n * #increment) }
+    } else {
+        quote! { (#address + n * #increment) as *const _ }
+    };
+
+    Some(quote! {
+        #[doc = #comment]
+        pub fn #name(n: usize) -> Option<&'static #name_sc::#name_pc> {
+            if n < #dim {
+                Some(unsafe { &*#ptr })
+            } else {
+                None
+            }
+        }
+    })
+}
+
+/// Returns the interrupts of the peripherals of the SVD file `tree`, by
+/// peripheral name
+///
+/// `svd-parser` only keeps the first interrupt of each peripheral.
+fn peripheral_interrupts(tree: &xmltree::Element)
+                         -> Result<BTreeMap<String, Vec<svd::Interrupt>>> {
+    let mut interrupts = BTreeMap::new();
+    for p in tree.get_child("peripherals")
+        .iter()
+        .flat_map(|ps| ps.children.iter()) {
+        let name = child_text(p, "name").unwrap_or("");
+
+        let mut is = vec![];
+        for i in p.children.iter().filter(|i| i.name == "interrupt") {
+            let iname = child_text(i, "name").unwrap_or("");
+            let value = child_text(i, "value").unwrap_or("");
+            is.push(svd::Interrupt {
+                name: iname.to_owned(),
+                description: child_text(i, "description")
+                    .map(|s| s.to_owned()),
+                value: try!(parse_u32(value).ok_or_else(|| {
+                    Error::InvalidValue {
+                        path: format!("{}.{}", name, iname),
+                        element: "value".to_owned(),
+                        value: value.to_owned(),
+                    }
+                })),
+            });
+        }
+
+        if !is.is_empty() {
+            interrupts.insert(name.to_owned(), is);
+        }
+    }
+
+    Ok(interrupts)
+}
+
+/// Returns the name of the symbol that `gen_linker_script` defines for the
+/// register block of the peripheral `p`
+///
+/// The symbol is not named after the peripheral because the interrupt
+/// handlers often are, e.g. `TIM2`, and the two would clash.
+fn block_symbol(p: &Peripheral) -> String {
+    format!("{}_REGISTER_BLOCK", p.name.to_constant_case())
+}
+
+/// Generates a linker script that defines one symbol per peripheral of the
+/// device; the address of the symbol is the base address of the peripheral
+///
+/// `gen_extern_statics` generates the matching declarations.
+pub fn gen_linker_script(d: &Device) -> Result<String> {
+    gen_linker_script_with(d, &Options::default())
+}
+
+/// Like `gen_linker_script` but with the given options; the instances of the
+/// `peripheral_arrays` get one symbol each
+pub fn gen_linker_script_with(d: &Device, opts: &Options) -> Result<String> {
+    let mut out = String::new();
+
+    for p in try!(peripherals(d, &opts.peripheral_arrays)) {
+        if p.registers.is_none() {
+            continue;
+        }
+
+        out.push_str(&format!("{} = 0x{:08x};\n",
+                              block_symbol(&p),
+                              p.base_address));
+    }
+
+    Ok(out)
+}
+
+/// Generates the `extern "C"` declarations of the symbols defined by the
+/// linker script that `gen_linker_script` generates
+///
+/// The declarations refer to the register block types that `gen_device`
+/// generates so they must be placed next to them.
+pub fn gen_extern_statics(d: &Device) -> Result<Tokens> {
+    gen_extern_statics_with(d, &Options::default())
+}
+
+/// Like `gen_extern_statics` but with the given options, which must be the
+/// ones passed to `gen_device_with`
+pub fn gen_extern_statics_with(d: &Device, opts: &Options) -> Result<Tokens> {
+    let mut statics = vec![];
+
+    for p in try!(peripherals(d, &opts.peripheral_arrays)) {
+        if p.registers.is_none() {
+            continue;
+        }
+
+        let name_pc = Ident::new(p.name.to_pascal_case());
+        let name_sc = Ident::new(p.name.to_snake_case().sanitize());
+        let symbol = Ident::new(block_symbol(&p));
+        let comment = &p.description
+            .as_ref()
+            .map(|d| respace(d))
+            .unwrap_or_else(|| p.name.clone())[..];
+
+        statics.push(quote! {
+            #[doc = #comment]
+            pub static mut #symbol: #name_sc::#name_pc;
+        });
+    }
+
+    Ok(quote! {
+        // NOTE the register blocks are only accessed from Rust
+        #[allow(improper_ctypes)]
+        extern "C" {
+            #(#statics)*
+        }
+    })
+}
+
+/// Returns the interrupts of the device sorted by their number
+///
+/// The interrupts that are shared by several peripherals are listed once.
+/// Two interrupts with the same name but different numbers, or with the same
+/// number but different names, are reported as errors.
+fn interrupts(d: &Device, opts: &Options) -> Result<Vec<svd::Interrupt>> {
+    let d = try!(expand_peripheral_arrays(d, &opts.peripheral_arrays));
+
+    // (peripheral, interrupt)
+    let mut interrupts: Vec<(&str, svd::Interrupt)> = vec![];
+    for p in &d.peripherals {
+        // the instances of a peripheral array share the interrupts of the
+        // array
+        let name = array_of(&opts.peripheral_arrays, &p.name);
+        let is = match opts.peripheral_interrupts.get(name) {
+            Some(is) => is.clone(),
+            None => p.interrupt.iter().cloned().collect(),
+        };
+
+        for i in is {
+            let name = i.name.to_constant_case();
+            if let Some(&(op, ref o)) = interrupts.iter().find(|&&(_, ref o)| {
+                o.value == i.value || o.name.to_constant_case() == name
+            }) {
+                if o.value == i.value && o.name.to_constant_case() == name {
+                    continue;
+                }
+
+                return Err(Error::DuplicateInterrupt {
+                    path: format!("{}.{}", p.name, i.name),
+                    value: i.value,
+                    other: format!("{}.{}", op, o.name),
+                    other_value: o.value,
+                });
+            }
+
+            interrupts.push((&p.name, i));
+        }
+    }
+
+    let mut interrupts = interrupts.into_iter()
+        .map(|(_, i)| i)
+        .collect::<Vec<_>>();
+    interrupts.sort_by_key(|i| i.value);
+    Ok(interrupts)
+}
+
+/// Generates the `Interrupt` enumeration and the vector table
+///
+/// The vector table is only available when the `rt` feature of the generated
+/// crate is enabled. Its entries are weakly linked: the interrupt handlers
+/// that the application doesn't define default to `DefaultHandler` through the
+/// linker script generated by `gen_device_x`.
+///
+/// The number of an interrupt must fit in a `u8`; Cortex-M devices have at
+/// most 240 interrupts.
+fn gen_interrupts(d: &Device, opts: &Options) -> Result<Tokens> {
+    let interrupts = try!(interrupts(d, opts));
+
+    let mut variants = vec![];
+    let mut arms = vec![];
+    let mut handlers = vec![];
+    let mut vectors = vec![];
+    for i in &interrupts {
+        let name = Ident::new(i.name.to_constant_case());
+        if i.value > 255 {
+            return Err(Error::InvalidValue {
+                path: i.name.clone(),
+                element: "value".to_owned(),
+                value: i.value.to_string(),
+            });
+        }
+        let value = i.value as u8;
+        let comment = &match i.description {
+            Some(ref description) => {
+                format!("{} - {}", i.value, respace(description))
+            }
+            None => i.value.to_string(),
+        }[..];
+
+        variants.push(quote! {
+            #[doc = #comment]
+            #name
+        });
+        arms.push(quote! {
+            Interrupt::#name => #value
+        });
+        handlers.push(quote! {
+            fn #name();
+        });
+
+        while vectors.len() < i.value as usize {
+            vectors.push(quote! { None });
+        }
+        vectors.push(quote! { Some(#name) });
+    }
+
+    let n = vectors.len();
+    let nr = if arms.is_empty() {
+        quote! {
+            match *self {}
+        }
+    } else {
+        quote! {
+            match *self {
+                #(#arms),*
+            }
+        }
+    };
+
+    Ok(quote! {
+        /// Enumeration of all the interrupts
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum Interrupt {
+            #(#variants),*
+        }
+
+        /// Trait implemented by the interrupt enumerations
+        ///
+        /// This trait is `unsafe` to implement because `nr` must return the
+        /// position of the interrupt in the vector table.
+        pub unsafe trait Nr {
+            /// Returns the number of the interrupt
+            fn nr(&self) -> u8;
+        }
+
+        unsafe impl Nr for Interrupt {
+            fn nr(&self) -> u8 {
+                #nr
+            }
+        }
+
+        #[cfg(feature = "rt")]
+        extern "C" {
+            #(#handlers)*
+        }
+
+        /// The interrupts part of the vector table
+        #[cfg(feature = "rt")]
+        #[doc(hidden)]
+        #[link_section = ".vector_table.interrupts"]
+        #[no_mangle]
+        pub static INTERRUPTS: [Option<unsafe extern "C" fn()>; #n] = [
+            #(#vectors),*
+        ];
+    })
+}
+
+/// Generates the linker script fragment, `device.x`, that makes the handlers
+/// of the interrupts of the device default to `DefaultHandler`
+pub fn gen_device_x(d: &Device) -> Result<String> {
+    gen_device_x_with(d, &Options::default())
+}
+
+/// Like `gen_device_x` but with the given options
+pub fn gen_device_x_with(d: &Device, opts: &Options) -> Result<String> {
+    let mut out = String::new();
+
+    for i in try!(interrupts(d, opts)) {
+        out.push_str(&format!("PROVIDE({} = DefaultHandler);\n",
+                              i.name.to_constant_case()));
+    }
+
+    Ok(out)
+}
+
+/// Generates the handle of the peripheral `p`
+///
+/// The handle is a zero sized type that owns the register block of the
+/// peripheral; it dereferences to the register block. In bus mode the
+/// register block is looked up with `register_block`, which hands out host
+/// memory when mocking or simulating.
+fn gen_handle(p: &Peripheral, opts: &Options) -> Tokens {
+    let name_pc = Ident::new(p.name.to_pascal_case());
+    let name_sc = Ident::new(p.name.to_snake_case().sanitize());
+    let name_cc = Ident::new(p.name.to_constant_case());
+    let address = hex(p.base_address);
+    let comment = &p.description
+        .as_ref()
+        .map(|d| respace(d))
+        .unwrap_or_else(|| p.name.clone())[..];
+    let ptr = if opts.bus {
+        quote! { register_block(#address) }
+    } else {
+        quote! { #address as *const _ }
+    };
+
+    quote! {
+        #[doc = #comment]
+        #[allow(non_camel_case_types)]
+        pub struct #name_cc {
+            _marker: ::core::marker::PhantomData<*const ()>,
+        }
+
+        unsafe impl Send for #name_cc {}
+
+        impl #name_cc {
+            /// Returns a pointer to the register block
+            pub fn ptr() -> *const #name_sc::#name_pc {
+                #ptr
+            }
+        }
+
+        impl ::core::ops::Deref for #name_cc {
+            type Target = #name_sc::#name_pc;
+
+            fn deref(&self) -> &#name_sc::#name_pc {
+                unsafe { &*#name_cc::ptr() }
+            }
+        }
+
+        impl ::core::ops::DerefMut for #name_cc {
+            fn deref_mut(&mut self) -> &mut #name_sc::#name_pc {
+                unsafe { &mut *(#name_cc::ptr() as *mut _) }
+            }
+        }
+    }
+}
+
+/// Generates the method that maps the register block of the peripheral `p`
+/// from a file, for the `linux` module
+fn gen_handle_map(p: &Peripheral) -> Tokens {
+    let name_pc = Ident::new(p.name.to_pascal_case());
+    let name_sc = Ident::new(p.name.to_snake_case().sanitize());
+    let name_cc = Ident::new(p.name.to_constant_case());
+    let address = hex(p.base_address);
+
+    quote! {
+        #[cfg(feature = "linux")]
+        impl #name_cc {
+            /// Maps the register block from `path`, e.g. `/dev/mem`, at its
+            /// physical address; the handle is consumed so that the register
+            /// block has a single owner
+            pub fn map<P>(self, path: P)
+                          -> ::std::io::Result<linux::Block<#name_sc::#name_pc>>
+                where P: AsRef<::std::path::Path>
+            {
+                unsafe { linux::Block::new(path, #address) }
+            }
+        }
+    }
+}
+
+/// Generates the `linux` module, which maps register blocks from files into
+/// memory
+fn gen_linux() -> Tokens {
+    let mut items = vec![];
+
+    items.push(quote! {
+        use std::fs::OpenOptions;
+        use std::io;
+        use std::marker::PhantomData;
+        use std::mem;
+        use std::ops::{Deref, DerefMut};
+        use std::os::unix::io::AsRawFd;
+        use std::path::Path;
+        use std::ptr;
+
+        use libc;
+    });
+
+    items.push(quote! {
+        /// Region of a file mapped into memory
+        ///
+        /// The region is unmapped when the `Mapping` is dropped.
+        pub struct Mapping {
+            // start of the mapping, which is page aligned
+            base: *mut u8,
+            // offset of the region from `base`
+            offset: usize,
+            len: usize,
+        }
+
+        impl Mapping {
+            /// Maps `len` bytes of the file at `path`, starting at `offset`
+            ///
+            /// `offset` doesn't need to be page aligned. For `/dev/mem` it's a
+            /// physical address; for UIO devices, e.g. `/dev/uio0`, the `n`-th
+            /// memory region starts at `n` times the page size.
+            pub fn new<P>(path: P,
+                          offset: u64,
+                          len: usize)
+                          -> io::Result<Mapping>
+                where P: AsRef<Path>
+            {
+                let file = try!(OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path));
+
+                let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+                let start = offset - offset % page;
+                let delta = (offset - start) as usize;
+                // NOTE `mmap64` takes a 64-bit offset even on 32-bit targets,
+                // where physical addresses can be wider than `off_t`
+                let base = unsafe {
+                    libc::mmap64(ptr::null_mut(),
+                                 len + delta,
+                                 libc::PROT_READ | libc::PROT_WRITE,
+                                 libc::MAP_SHARED,
+                                 file.as_raw_fd(),
+                                 start as libc::off64_t)
+                };
+
+                if base == libc::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(Mapping {
+                    base: base as *mut u8,
+                    offset: delta,
+                    len: len,
+                })
+            }
+
+            /// Returns a pointer to the start of the region
+            pub fn as_ptr(&self) -> *mut u8 {
+                unsafe { self.base.offset(self.offset as isize) }
+            }
+
+            /// Returns the length of the region, in bytes
+            pub fn len(&self) -> usize {
+                self.len
+            }
+        }
+    });
+
+    items.push(quote! {
+        impl Mapping {
+            /// Returns the register block located `offset` bytes into the
+            /// region
+            ///
+            /// # Panics
+            ///
+            /// If the register block doesn't fit in the region or is
+            /// misaligned
+            ///
+            /// # Unsafety
+            ///
+            /// `T` must be a register block
+            pub unsafe fn block<T>(&self, offset: usize) -> &T {
+                &*self.block_ptr::<T>(offset)
+            }
+
+            /// Returns the register block located `offset` bytes into the
+            /// region, mutably
+            ///
+            /// # Panics
+            ///
+            /// If the register block doesn't fit in the region or is
+            /// misaligned
+            ///
+            /// # Unsafety
+            ///
+            /// `T` must be a register block
+            pub unsafe fn block_mut<T>(&mut self, offset: usize) -> &mut T {
+                &mut *self.block_ptr::<T>(offset)
+            }
+
+            unsafe fn block_ptr<T>(&self, offset: usize) -> *mut T {
+                assert!(offset + mem::size_of::<T>() <= self.len);
+                let ptr = self.as_ptr().offset(offset as isize);
+                assert!(ptr as usize % mem::align_of::<T>() == 0);
+                ptr as *mut T
+            }
+        }
+
+        impl Drop for Mapping {
+            fn drop(&mut self) {
+                unsafe {
+                    libc::munmap(self.base as *mut libc::c_void,
+                                 self.len + self.offset);
+                }
+            }
+        }
+    });
+
+    items.push(quote! {
+        /// Register block mapped from a file; it dereferences to `T`
+        pub struct Block<T> {
+            mapping: Mapping,
+            _marker: PhantomData<T>,
+        }
+
+        impl<T> Block<T> {
+            /// Maps the register block located `offset` bytes into the file
+            /// at `path`; see `Mapping::new`
+            ///
+            /// # Unsafety
+            ///
+            /// `T` must be a register block
+            pub unsafe fn new<P>(path: P, offset: u64) -> io::Result<Block<T>>
+                where P: AsRef<Path>
+            {
+                let len = mem::size_of::<T>();
+                let mapping = try!(Mapping::new(path, offset, len));
+                // check the alignment
+                mapping.block::<T>(0);
+
+                Ok(Block {
+                    mapping: mapping,
+                    _marker: PhantomData,
+                })
+            }
+        }
+
+        impl<T> Deref for Block<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                unsafe { &*(self.mapping.as_ptr() as *const T) }
+            }
+        }
+
+        impl<T> DerefMut for Block<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                unsafe { &mut *(self.mapping.as_ptr() as *mut T) }
+            }
+        }
+    });
+
+    quote! {
+        /// Register blocks mapped from files, e.g. `/dev/mem` or a UIO device,
+        /// for Linux user space programs
+        ///
+        /// The handles of the peripherals assume that the register blocks are
+        /// at their physical address; use their `map` method, which returns a
+        /// `Block`, instead.
+        #[cfg(feature = "linux")]
+        pub mod linux {
+            #(#items)*
+        }
+    }
+}
+
+/// Generates the `Peripherals` singleton, which owns the handles of the
+/// peripherals named `names`
+fn gen_peripherals(names: &[String]) -> Tokens {
+    let mut fields = vec![];
+    let mut exprs = vec![];
+
+    for name in names {
+        let name_cc = Ident::new(name.to_constant_case());
+        let comment = &name[..];
+
+        fields.push(quote! {
+            #[doc = #comment]
+            pub #name_cc: #name_cc
+        });
+        exprs.push(quote! {
+            #name_cc: #name_cc { _marker: ::core::marker::PhantomData }
+        });
+    }
+
+    quote! {
+        /// All the peripherals
+        #[allow(non_snake_case)]
+        pub struct Peripherals {
+            #(#fields),*
+        }
+
+        // Has `Peripherals::take` (or `steal`) been called?
+        static mut DEVICE_PERIPHERALS: bool = false;
+
+        impl Peripherals {
+            /// Returns all the peripherals *once*
+            ///
+            /// **NOTE** This method is not reentrant; call it before enabling
+            /// the interrupts, or from within a critical section.
+            pub fn take() -> Option<Self> {
+                unsafe {
+                    if DEVICE_PERIPHERALS {
+                        None
+                    } else {
+                        Some(Peripherals::steal())
+                    }
+                }
+            }
+
+            /// Unchecked version of `Peripherals::take`
+            ///
+            /// This hands out the peripherals even if they have already been
+            /// taken, which breaks the ownership guarantees of the handles.
+            pub unsafe fn steal() -> Self {
+                DEVICE_PERIPHERALS = true;
+
+                Peripherals {
+                    #(#exprs),*
+                }
+            }
+        }
+    }
+}
+
+/// Finds the peripheral named `name`. Comparison is case insensitive.
+fn find_peripheral<'a>(d: &'a Device, name: &str) -> Option<&'a Peripheral> {
+    let name = name.to_lowercase();
+    d.peripherals.iter().find(|p| p.name.to_lowercase() == name)
+}
+
+/// Resolves the `derivedFrom` attributes of the device
+///
+/// Returns a copy of the device where no peripheral is derived from another
+/// one, and where the enumerated values that are derived from other
+/// enumerated values list the values of their base. Chains of `derivedFrom`
+/// are followed; cycles are reported as errors.
+///
+/// A derived peripheral takes the properties that it doesn't specify from its
+/// base peripheral. Its registers are added to the registers of the base
+/// peripheral; if both have a register with the same name, the one of the
+/// derived peripheral replaces the other.
+///
+/// **NOTE** `svd-parser` doesn't parse the `derivedFrom` attribute of
+/// registers and fields; `parse` resolves these.
+pub fn resolve(d: &Device) -> Result<Device> {
+    let mut peripherals = vec![];
+    for p in &d.peripherals {
+        peripherals.push(try!(resolve_peripheral(d, p, &mut vec![])));
+    }
+
+    let mut device = Device {
+        name: d.name.clone(),
+        peripherals: peripherals,
+        defaults: d.defaults,
+    };
+
+    // enumerated values are resolved once the registers of all the
+    // peripherals are known
+    let resolved = device.clone();
+    for p in &mut device.peripherals {
+        for r in p.registers.iter_mut().flat_map(|rs| rs.iter_mut()) {
+            let r = match *r {
+                Register::Single(ref mut info) |
+                Register::Array(ref mut info, _) => info,
+            };
+            let rname = r.name.clone();
+            for f in r.fields.iter_mut().flat_map(|fs| fs.iter_mut()) {
+                if let Some(ref mut evs) = f.enumerated_values {
+                    let path = format!("{}.{}.{}", p.name, rname, f.name);
+                    let values = try!(resolve_enumerated_values(&resolved,
+                                                                &p.name,
+                                                                &rname,
+                                                                &path,
+                                                                evs,
+                                                                &mut vec![]));
+                    evs.values = values;
+                    evs.derived_from = None;
+                }
+            }
+        }
+    }
+
+    Ok(device)
+}
+
+/// Resolves the `derivedFrom` of the peripheral `p`; `chain` lists the
+/// peripherals that derive from `p`
+fn resolve_peripheral(d: &Device,
+                      p: &Peripheral,
+                      chain: &mut Vec<String>)
+                      -> Result<Peripheral> {
+    let bn = match p.derived_from {
+        Some(ref bn) => bn,
+        None => return Ok(p.clone()),
+    };
+
+    let name = p.name.to_lowercase();
+    chain.push(p.name.clone());
+    if chain[..chain.len() - 1].iter().any(|n| n.to_lowercase() == name) {
+        return Err(Error::DerivedFromCycle {
+            path: chain[0].clone(),
+            cycle: chain.clone(),
+        });
+    }
+
+    let bp = try!(find_peripheral(d, bn).ok_or_else(|| {
+        Error::DerivedFromNotFound {
+            path: p.name.clone(),
+            base: bn.clone(),
+        }
+    }));
+    let bp = try!(resolve_peripheral(d, bp, chain));
+
+    Ok(merge(p, &bp))
+}
+
+/// Merges the peripheral `p`, which is derived from `bp`, with its base
+/// peripheral. The properties that `p` doesn't specify are taken from `bp`;
+/// the registers of `p` are added to, or replace, the registers of `bp`.
+fn merge(p: &Peripheral, bp: &Peripheral) -> Peripheral {
+    let registers = match (p.registers.as_ref(), bp.registers.as_ref()) {
+        (Some(registers), Some(base_registers)) => {
+            let mut merged = base_registers.clone();
+            for r in registers {
+                match merged.iter().position(|br| br.name == r.name) {
+                    Some(i) => merged[i] = r.clone(),
+                    None => merged.push(r.clone()),
+                }
+            }
+            Some(merged)
+        }
+        (registers, base_registers) => registers.or(base_registers).cloned(),
+    };
+
+    Peripheral {
+        name: p.name.clone(),
+        base_address: p.base_address,
+        derived_from: None,
+        group_name: p.group_name.clone().or_else(|| bp.group_name.clone()),
+        description: p.description.clone().or_else(|| bp.description.clone()),
+        interrupt: p.interrupt.clone().or_else(|| bp.interrupt.clone()),
+        registers: registers,
+    }
+}
+
+/// Returns the values of `evs`, which belong to the field at `path`, taking
+/// `derivedFrom` into account; `chain` lists the fields whose enumerated
+/// values derive from `evs`
+///
+/// The base enumerated values are looked up by name, first in the register
+/// `rname`, then in the peripheral `pname`, and then in the whole device. If
+/// `derivedFrom` is a path, e.g. `GPIOA.MODER.MODER0.Mode`, only its last
+/// component is used.
+fn resolve_enumerated_values(d: &Device,
+                             pname: &str,
+                             rname: &str,
+                             path: &str,
+                             evs: &EnumeratedValues,
+                             chain: &mut Vec<String>)
+                             -> Result<Vec<svd::EnumeratedValue>> {
+    let base = match evs.derived_from {
+        Some(ref base) => base,
+        None => return Ok(evs.values.clone()),
+    };
+
+    chain.push(path.to_owned());
+    if chain[..chain.len() - 1].iter().any(|p| p == path) {
+        return Err(Error::DerivedFromCycle {
+            path: chain[0].clone(),
+            cycle: chain.clone(),
+        });
+    }
+
+    let name = base.rsplit('.').next().unwrap_or(base);
+    let mut candidates = vec![];
+    for p in &d.peripherals {
+        for r in p.registers.iter().flat_map(|rs| rs.iter()) {
+            for f in r.fields.iter().flat_map(|fs| fs.iter()) {
+                if let Some(ref bevs) = f.enumerated_values {
+                    if bevs.name.as_ref().map(|n| &n[..]) == Some(name) {
+                        // prefer the closest definition
+                        let rank = match (p.name == pname, r.name == rname) {
+                            (true, true) => 0,
+                            (true, false) => 1,
+                            _ => 2,
+                        };
+                        let bpath = format!("{}.{}.{}", p.name, r.name, f.name);
+                        candidates.push((rank, p, r, bpath, bevs));
+                    }
+                }
+            }
+        }
+    }
+
+    let (_, p, r, bpath, bevs) = try!(candidates.into_iter()
+        .min_by_key(|&(rank, _, _, _, _)| rank)
+        .ok_or_else(|| {
+            Error::DerivedFromNotFound {
+                path: path.to_owned(),
+                base: base.clone(),
+            }
+        }));
+
+    resolve_enumerated_values(d, &p.name, &r.name, &bpath, bevs, chain)
+}
+
+/// Parts of a register block that `svd-parser` doesn't parse
+#[derive(Clone, Debug, Default)]
+pub struct Extras {
+    /// Clusters of registers
+    pub clusters: Vec<Cluster>,
+    /// Reset values of the registers, by name. `svd-parser` reads them as
+    /// 32-bit values; these are read as 64-bit ones
+    pub resets: BTreeMap<String, Reset>,
+    /// Semantics of the registers, e.g. `CR`, and of their fields, e.g.
+    /// `CR.EN`; the ones that have none are not listed
+    pub semantics: BTreeMap<String, Semantics>,
+}
+
+impl Extras {
+    fn is_empty(&self) -> bool {
+        self.clusters.is_empty() && self.resets.is_empty() &&
+        self.semantics.is_empty()
+    }
+
+    /// Returns the semantic `property` of the register `r`, or of its field
+    /// `f`. Fields inherit the semantics of their register.
+    fn semantic<T, F>(&self, r: &str, f: Option<&str>, property: F) -> Option<T>
+        where F: Fn(&Semantics) -> Option<T>
+    {
+        f.and_then(|f| self.semantics.get(&format!("{}.{}", r, f)))
+            .and_then(&property)
+            .or_else(|| self.semantics.get(r).and_then(&property))
+    }
+}
+
+/// Semantics of a register, or field, that `svd-parser` doesn't parse
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Semantics {
+    /// Effect of the writes
+    pub modified_write_values: Option<ModifiedWriteValues>,
+    /// Side effect of the reads
+    pub read_action: Option<ReadAction>,
+    /// Values that can be written
+    pub write_constraint: Option<WriteConstraint>,
+}
+
+/// Effect of writing a value to a register, or field, as described by its
+/// `modifiedWriteValues`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModifiedWriteValues {
+    /// Writing a one clears the bit; writing a zero has no effect
+    OneToClear,
+    /// Writing a one sets the bit; writing a zero has no effect
+    OneToSet,
+    /// Writing a one toggles the bit; writing a zero has no effect
+    OneToToggle,
+    /// Writing a zero clears the bit; writing a one has no effect
+    ZeroToClear,
+    /// Writing a zero sets the bit; writing a one has no effect
+    ZeroToSet,
+    /// Writing a zero toggles the bit; writing a one has no effect
+    ZeroToToggle,
+    /// Any write clears the bits
+    Clear,
+    /// Any write sets the bits
+    Set,
+    /// Writes store the written value
+    Modify,
+}
+
+impl ModifiedWriteValues {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "oneToClear" => ModifiedWriteValues::OneToClear,
+            "oneToSet" => ModifiedWriteValues::OneToSet,
+            "oneToToggle" => ModifiedWriteValues::OneToToggle,
+            "zeroToClear" => ModifiedWriteValues::ZeroToClear,
+            "zeroToSet" => ModifiedWriteValues::ZeroToSet,
+            "zeroToToggle" => ModifiedWriteValues::ZeroToToggle,
+            "clear" => ModifiedWriteValues::Clear,
+            "set" => ModifiedWriteValues::Set,
+            "modify" => ModifiedWriteValues::Modify,
+            _ => return None,
+        })
+    }
+
+    /// Value that has no effect when written to a bit, if any
+    fn neutral(&self) -> Option<bool> {
+        match *self {
+            ModifiedWriteValues::OneToClear |
+            ModifiedWriteValues::OneToSet |
+            ModifiedWriteValues::OneToToggle => Some(false),
+            ModifiedWriteValues::ZeroToClear |
+            ModifiedWriteValues::ZeroToSet |
+            ModifiedWriteValues::ZeroToToggle => Some(true),
+            ModifiedWriteValues::Clear |
+            ModifiedWriteValues::Set |
+            ModifiedWriteValues::Modify => None,
+        }
+    }
+}
+
+/// The `resetValue` and `resetMask` of a register, including the ones it
+/// inherits from its cluster or device
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reset {
+    pub value: Option<u64>,
+    pub mask: Option<u64>,
+}
+
+/// Side effect of reading a register, or field, as described by its
+/// `readAction`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadAction {
+    /// Reads clear the bits
+    Clear,
+    /// Reads set the bits
+    Set,
+    /// Reads modify the bits in some other way
+    Modify,
+    /// Reads have side effects on other registers, or on the hardware
+    ModifyExternal,
+}
+
+impl ReadAction {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "clear" => ReadAction::Clear,
+            "set" => ReadAction::Set,
+            "modify" => ReadAction::Modify,
+            "modifyExternal" => ReadAction::ModifyExternal,
+            _ => return None,
+        })
+    }
+
+    /// Describes what reading does to `target`, the element that has this
+    /// action
+    fn effect(&self, target: &str) -> String {
+        match *self {
+            ReadAction::Clear => format!("clears {}", target),
+            ReadAction::Set => format!("sets {}", target),
+            ReadAction::Modify => format!("modifies {}", target),
+            ReadAction::ModifyExternal => {
+                "changes the state of the peripheral".to_owned()
+            }
+        }
+    }
+}
+
+/// Values that can be written to a register, or field, as described by its
+/// `writeConstraint`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteConstraint {
+    /// Only the value that was last read can be written
+    WriteAsRead,
+    /// Only the enumerated values of the field can be written
+    UseEnumeratedValues,
+    /// Only the values in this inclusive range can be written
+    Range(u64, u64),
+}
+
+/// A cluster of registers, which is laid out as a register block nested in
+/// the register block of its peripheral, or cluster
+#[derive(Clone, Debug)]
+pub struct Cluster {
+    /// Name of the cluster, e.g. `CH%s`
+    pub name: String,
+    pub description: Option<String>,
+    /// Offset of the cluster from the start of the enclosing register block
+    pub address_offset: u32,
+    /// Number of elements and stride, if this is an array of clusters
+    pub array_info: Option<RegisterArrayInfo>,
+    pub registers: Vec<Register>,
+    /// The parts of the cluster that `svd-parser` doesn't parse, like the
+    /// clusters nested in it
+    pub extras: Extras,
+}
+
+/// Returns the parts of the register blocks of the SVD file `xml` that
+/// `svd-parser` doesn't parse, like the register clusters, by peripheral
+/// name
+///
+/// The peripherals whose register blocks have no such parts are not listed.
+pub fn peripheral_extras(xml: &str) -> Result<BTreeMap<String, Extras>> {
+    extras(&try!(parse_xml(xml)))
+}
+
+/// Returns the extras of the peripherals of the SVD file `tree`
+fn extras(tree: &xmltree::Element) -> Result<BTreeMap<String, Extras>> {
+    let defaults = try!(reset(tree, Reset::default()));
+
+    let mut extras = BTreeMap::new();
+    for p in tree.get_child("peripherals")
+        .iter()
+        .flat_map(|ps| ps.children.iter()) {
+        let name = child_text(p, "name").unwrap_or("");
+        if let Some(block) = p.get_child("registers") {
+            let e = try!(block_extras(block, defaults)
+                .map_err(|e| e.within(name)));
+            if !e.is_empty() {
+                extras.insert(name.to_owned(), e);
+            }
+        }
+    }
+
+    Ok(extras)
+}
+
+/// Returns the extras of `block`, the registers of a peripheral or a cluster;
+/// its registers inherit the `defaults` reset value and mask
+fn block_extras(block: &xmltree::Element, defaults: Reset) -> Result<Extras> {
+    let mut clusters = vec![];
+    for c in block.children.iter().filter(|c| c.name == "cluster") {
+        let name = child_text(c, "name").unwrap_or("");
+        clusters.push(try!(cluster(c, defaults).map_err(|e| e.within(name))));
+    }
+
+    let mut resets = BTreeMap::new();
+    let mut semantics = BTreeMap::new();
+    for r in block.children.iter().filter(|r| r.name == "register") {
+        let name = child_text(r, "name").unwrap_or("");
+        let reset = try!(reset(r, defaults));
+        if reset != Reset::default() {
+            resets.insert(name.to_owned(), reset);
+        }
+
+        let rs = try!(parse_semantics(r).map_err(|e| e.within(name)));
+        if rs != Semantics::default() {
+            semantics.insert(name.to_owned(), rs);
+        }
+
+        for f in r.get_child("fields")
+            .iter()
+            .flat_map(|fs| fs.children.iter()) {
+            let fname = child_text(f, "name").unwrap_or("");
+            let fs = try!(parse_semantics(f)
+                .map_err(|e| e.within(fname).within(name)));
+            if fs != Semantics::default() {
+                semantics.insert(format!("{}.{}", name, fname), fs);
+            }
+        }
+    }
+
+    Ok(Extras {
+        clusters: clusters,
+        resets: resets,
+        semantics: semantics,
+    })
+}
+
+/// Parses the semantics of `e`, a register or a field
+fn parse_semantics(e: &xmltree::Element) -> Result<Semantics> {
+    let invalid = |element: &str, value: &str| {
+        Error::InvalidValue {
+            path: String::new(),
+            element: element.to_owned(),
+            value: value.to_owned(),
+        }
+    };
+
+    let modified_write_values = match child_text(e, "modifiedWriteValues") {
+        Some(v) => {
+            Some(try!(ModifiedWriteValues::parse(v)
+                .ok_or_else(|| invalid("modifiedWriteValues", v))))
+        }
+        None => None,
+    };
+
+    let read_action = match child_text(e, "readAction") {
+        Some(v) => {
+            Some(try!(ReadAction::parse(v)
+                .ok_or_else(|| invalid("readAction", v))))
+        }
+        None => None,
+    };
+
+    let write_constraint = match e.get_child("writeConstraint") {
+        Some(wc) => Some(try!(parse_write_constraint(wc))),
+        None => None,
+    };
+
+    Ok(Semantics {
+        modified_write_values: modified_write_values,
+        read_action: read_action,
+        write_constraint: write_constraint,
+    })
+}
+
+/// Parses the `writeConstraint` element `wc`
+fn parse_write_constraint(wc: &xmltree::Element) -> Result<WriteConstraint> {
+    let invalid = || {
+        let mut xml = vec![];
+        wc.write(&mut xml);
+        Error::InvalidValue {
+            path: String::new(),
+            element: "writeConstraint".to_owned(),
+            value: String::from_utf8_lossy(&xml).into_owned(),
+        }
+    };
+
+    if child_text(wc, "writeAsRead") == Some("true") {
+        Ok(WriteConstraint::WriteAsRead)
+    } else if child_text(wc, "useEnumeratedValues") == Some("true") {
+        Ok(WriteConstraint::UseEnumeratedValues)
+    } else if let Some(range) = wc.get_child("range") {
+        match (child_text(range, "minimum").and_then(parse_u64),
+               child_text(range, "maximum").and_then(parse_u64)) {
+            (Some(min), Some(max)) if min <= max => {
+                Ok(WriteConstraint::Range(min, max))
+            }
+            _ => Err(invalid()),
+        }
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Reads the `resetValue` and `resetMask` of `e`, a register, a cluster or a
+/// device, as 64-bit values; the ones it doesn't specify are taken from
+/// `defaults`
+fn reset(e: &xmltree::Element, defaults: Reset) -> Result<Reset> {
+    let read = |property: &str| match child_text(e, property) {
+        None => Ok(None),
+        Some(text) => {
+            parse_u64(text).map(Some).ok_or_else(|| {
+                Error::InvalidSvd {
+                    message: format!("the `{}` of `{}` is not a valid \
+                                      number",
+                                     property,
+                                     child_text(e, "name").unwrap_or("")),
+                }
+            })
+        }
+    };
+
+    Ok(Reset {
+        value: try!(read("resetValue")).or(defaults.value),
+        mask: try!(read("resetMask")).or(defaults.mask),
+    })
+}
+
+/// Parses the cluster `c`, whose registers inherit the `defaults` reset
+/// value and mask
+fn cluster(c: &xmltree::Element, defaults: Reset) -> Result<Cluster> {
+    let name = child_text(c, "name").unwrap_or("");
+    let address_offset = try!(child_text(c, "addressOffset")
+        .and_then(parse_u32)
+        .ok_or_else(|| {
+            Error::InvalidSvd {
+                message: format!("the cluster `{}` has no valid \
+                                  `addressOffset`",
+                                 name),
+            }
+        }));
+
+    Ok(Cluster {
+        name: name.to_owned(),
+        description: child_text(c, "description").map(String::from),
+        address_offset: address_offset,
+        array_info: try!(array_info(c)),
+        registers: try!(cluster_registers(c)),
+        extras: try!(block_extras(c, try!(reset(c, defaults)))),
+    })
+}
+
+/// Parses the registers of the cluster `c` using `svd-parser`
+///
+/// The registers take the `size`, `access`, `resetValue` and `resetMask` of
+/// the cluster if they don't specify their own.
+fn cluster_registers(c: &xmltree::Element) -> Result<Vec<Register>> {
+    let node = |name: &str, children: Vec<xmltree::Element>| {
+        xmltree::Element {
+            name: name.to_owned(),
+            attributes: HashMap::new(),
+            children: children,
+            text: None,
+        }
+    };
+    let leaf = |name: &str, text: &str| {
+        let mut leaf = node(name, vec![]);
+        leaf.text = Some(text.to_owned());
+        leaf
+    };
+
+    let mut registers = vec![];
+    for r in c.children.iter().filter(|r| r.name == "register") {
+        let mut r = r.clone();
+        for property in &["size", "access", "resetValue", "resetMask"] {
+            if r.get_child(*property).is_none() {
+                if let Some(value) = c.get_child(*property) {
+                    r.children.push(value.clone());
+                }
+            }
+        }
+        registers.push(r);
+    }
+
+    // NOTE `svd-parser` only parses whole devices
+    let device = node("device",
+                      vec![leaf("name", "CLUSTER"),
+                           node("peripherals",
+                                vec![node("peripheral",
+                                          vec![leaf("name", "CLUSTER"),
+                                               leaf("baseAddress", "0"),
+                                               node("registers",
+                                                    registers)])])]);
+    let mut xml = vec![];
+    device.write(&mut xml);
+
+    let d = try!(parse_device(&String::from_utf8_lossy(&xml)));
+    Ok(d.peripherals
+        .into_iter()
+        .next()
+        .and_then(|p| p.registers)
+        .unwrap_or_else(Vec::new))
+}
+
+/// Parses the `dim`, `dimIncrement` and `dimIndex` of `e`, a peripheral or a
+/// cluster; returns `None` if `e` has no `dim`
+fn array_info(e: &xmltree::Element) -> Result<Option<RegisterArrayInfo>> {
+    let dim = match child_text(e, "dim") {
+        Some(dim) => dim,
+        None => return Ok(None),
+    };
+    let invalid = || Error::InvalidDim { path: String::new() };
+
+    let dim = try!(parse_u32(dim).ok_or_else(&invalid));
+    let dim_increment = try!(child_text(e, "dimIncrement")
+        .and_then(parse_u32)
+        .ok_or_else(&invalid));
+    let dim_index = match child_text(e, "dimIndex") {
+        Some(index) => Some(try!(parse_dim_index(index).ok_or_else(&invalid))),
+        None => None,
+    };
+
+    if dim == 0 || !child_text(e, "name").unwrap_or("").contains("%s") ||
+       dim_index.as_ref().map_or(false, |i| i.len() != dim as usize) {
+        return Err(invalid());
+    }
+
+    Ok(Some(RegisterArrayInfo {
+        dim: dim,
+        dim_increment: dim_increment,
+        dim_index: dim_index,
+    }))
+}
+
+/// Parses a scaled non negative integer, e.g. `0x400` or `8`
+fn parse_u32(s: &str) -> Option<u32> {
+    parse_u64(s).and_then(|n| if n >> 32 == 0 { Some(n as u32) } else { None })
+}
+
+/// Parses a decimal, or hexadecimal (`0x` prefixed), 64-bit integer
+fn parse_u64(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Parses a `dimIndex`, e.g. `0-3` or `A,B,C`
+fn parse_dim_index(s: &str) -> Option<Vec<String>> {
+    if s.contains(',') {
+        Some(s.split(',').map(|i| i.trim().to_owned()).collect())
+    } else {
+        let mut parts = s.splitn(2, '-');
+        let start = parts.next().and_then(|n| n.trim().parse::<u32>().ok());
+        let end = parts.next().and_then(|n| n.trim().parse::<u32>().ok());
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => {
+                Some((start..end + 1).map(|i| i.to_string()).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the trimmed text of the child of `e` named `name`
+fn child_text<'a>(e: &'a xmltree::Element, name: &str) -> Option<&'a str> {
+    e.get_child(name).and_then(|c| c.text.as_ref()).map(|t| t.trim())
+}
+
+/// Returns the extras of the peripherals of the device, by name, with the
+/// peripheral arrays expanded and the `derivedFrom` of the peripherals
+/// resolved, like `resolve` does
+fn resolve_extras(d: &Device,
+                  opts: &Options)
+                  -> Result<BTreeMap<String, Extras>> {
+    let d = try!(expand_peripheral_arrays(d, &opts.peripheral_arrays));
+
+    let mut extras = BTreeMap::new();
+    for p in &d.peripherals {
+        let p_extras =
+            try!(resolve_peripheral_extras(&d, p, opts, &mut vec![]));
+        extras.insert(p.name.clone(), p_extras);
+    }
+
+    Ok(extras)
+}
+
+/// Resolves the extras of the peripheral `p`; `chain` lists the peripherals
+/// that derive from `p`
+fn resolve_peripheral_extras(d: &Device,
+                             p: &Peripheral,
+                             opts: &Options,
+                             chain: &mut Vec<String>)
+                             -> Result<Extras> {
+    // the instances of a peripheral array share the extras of the array
+    let extras = opts.peripheral_extras
+        .get(array_of(&opts.peripheral_arrays, &p.name))
+        .cloned()
+        .unwrap_or_default();
+
+    let bn = match p.derived_from {
+        Some(ref bn) => bn,
+        None => return Ok(extras),
+    };
+
+    let name = p.name.to_lowercase();
+    chain.push(p.name.clone());
+    if chain[..chain.len() - 1].iter().any(|n| n.to_lowercase() == name) {
+        return Err(Error::DerivedFromCycle {
+            path: chain[0].clone(),
+            cycle: chain.clone(),
+        });
+    }
+
+    let bp = try!(find_peripheral(d, bn).ok_or_else(|| {
+        Error::DerivedFromNotFound {
+            path: p.name.clone(),
+            base: bn.clone(),
+        }
+    }));
+    let mut merged = try!(resolve_peripheral_extras(d, bp, opts, chain));
+
+    // like the registers, the clusters of the derived peripheral are added to
+    // the ones of its base, or replace the ones that have the same name
+    for c in extras.clusters {
+        match merged.clusters.iter().position(|bc| bc.name == c.name) {
+            Some(i) => merged.clusters[i] = c,
+            None => merged.clusters.push(c),
+        }
+    }
+
+    // the registers that the derived peripheral lists replace the ones of its
+    // base, reset values and semantics included
+    for r in p.registers.iter().flat_map(|rs| rs.iter()) {
+        let prefix = format!("{}.", r.name);
+        merged.resets.remove(&r.name);
+        merged.semantics = merged.semantics
+            .into_iter()
+            .filter(|&(ref k, _)| *k != r.name && !k.starts_with(&prefix))
+            .collect();
+    }
+    merged.resets.extend(extras.resets);
+    merged.semantics.extend(extras.semantics);
+
+    Ok(merged)
+}
+
+#[doc(hidden)]
+pub fn gen_peripheral(p: &Peripheral, d: &Defaults) -> Result<Vec<Tokens>> {
+    gen_peripheral_(p, d, &Options::default(), &Extras::default())
+        .map_err(|e| e.within(&p.name))
+}
+
+/// Like `gen_peripheral` but with the given options; `p` is a peripheral of
+/// the device `d`, or an instance of one of its `peripheral_arrays`
+#[doc(hidden)]
+pub fn gen_peripheral_with(d: &Device,
+                           p: &Peripheral,
+                           opts: &Options)
+                           -> Result<Vec<Tokens>> {
+    let extras = try!(resolve_extras(d, opts));
+    let expanded = try!(expand_peripheral_arrays(d, &opts.peripheral_arrays));
+    let resolved = try!(resolve(&expanded));
+    let p = resolved.peripherals
+        .iter()
+        .find(|rp| rp.name == p.name)
+        .unwrap_or(p);
+
+    gen_peripheral_(p,
+                    &d.defaults,
+                    opts,
+                    extras.get(&p.name).unwrap_or(&Extras::default()))
+        .map_err(|e| e.within(&p.name))
+}
+
+/// Context of the code generated for a register block
+struct Scope<'a> {
+    /// Path from the module of the register block to the crate root, e.g.
+    /// `super` for the module of a peripheral
+    root: Tokens,
+    /// Parts of the register block that `svd-parser` doesn't parse
+    extras: &'a Extras,
+}
+
+impl<'a> Scope<'a> {
+    /// Scope of the register block of a peripheral
+    fn peripheral(extras: &'a Extras) -> Self {
+        Scope {
+            root: quote! { super },
+            extras: extras,
+        }
+    }
+}
+
+fn gen_peripheral_(p: &Peripheral,
+                   d: &Defaults,
+                   opts: &Options,
+                   extras: &Extras)
+                   -> Result<Vec<Tokens>> {
+    if p.derived_from.is_some() {
+        return Err(Error::UnresolvedDerivedFrom { path: String::new() });
+    }
+
+    let registers = try!(p.registers
+        .as_ref()
+        .ok_or_else(|| Error::NoRegisters { path: String::new() }));
+
+    let mut items = try!(gen_block(&p.name,
+                                   p.description.as_ref(),
+                                   registers,
+                                   None,
+                                   d,
+                                   opts,
+                                   &Scope::peripheral(extras)));
+
+    if opts.bus {
+        items.push(try!(gen_register_models(registers, extras, d)));
+    }
+
+    Ok(items)
+}
+
+/// Generates the register block `name`, which lays out the `registers` and
+/// the clusters of the `scope`, plus the types of its registers and the
+/// modules of its clusters
+///
+/// If `size` is given the register block is padded to that many bytes.
+fn gen_block(name: &str,
+             description: Option<&String>,
+             registers: &[Register],
+             size: Option<u32>,
+             d: &Defaults,
+             opts: &Options,
+             scope: &Scope)
+             -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+    let mut fields = vec![];
+    let mut accessors = vec![];
+    let mut offset = 0;
+    let mut i = 0;
+
+    let mut registers_ = try!(expand(registers, d));
+    registers_.extend(try!(expand_clusters(&scope.extras.clusters, d)));
+    registers_.sort_by_key(|r| r.offset);
+
+    // A register that starts before the end of the previous one overlaps with
+    // it. Such register becomes an alternate view of the register that owns
+    // that location.
+    let mut owners = vec![None; registers_.len()];
+    let mut end = 0;
+    let mut last = 0;
+    for (j, register) in registers_.iter().enumerate() {
+        if register.offset < end {
+            owners[j] = Some(last);
+        } else {
+            last = j;
+        }
+        end = cmp::max(end, register.offset + register.size);
+    }
+
+    for (j, register) in registers_.iter().enumerate() {
+        let mut comment = format!("0x{:02x} - {}",
+                                  register.offset,
+                                  respace(register.description));
+
+        let reg_ty = match register.ty {
+            Either::Left(ref ty) => Ident::from(&**ty),
+            Either::Right(ref ty) => Ident::from(&***ty),
+        };
+        let reg_ty = if let Some(dim) = register.dim {
+            let dim = dim as usize;
+            quote! { [#reg_ty; #dim] }
+        } else {
+            quote! { #reg_ty }
+        };
+        let reg_name = Ident::new(&*register.name);
+
+        if let Some(owner) = owners[j] {
+            comment.push_str(&format!("\n\nThis register overlaps with \
+                                       `{}`; this method returns an \
+                                       alternate view of the same location.",
+                                      registers_[owner].name));
+            let comment = &comment[..];
+            let reg_name_mut = Ident::new(format!("{}_mut", register.name));
+            let reg_offset = register.offset as usize;
+
+            accessors.push(quote! {
+                #[doc = #comment]
+                pub fn #reg_name(&self) -> &#reg_ty {
+                    unsafe {
+                        &*((self as *const Self as *const u8)
+                            .offset(#reg_offset as isize) as *const #reg_ty)
+                    }
+                }
+
+                #[doc = #comment]
+                pub fn #reg_name_mut(&mut self) -> &mut #reg_ty {
+                    unsafe {
+                        &mut *((self as *mut Self as *mut u8)
+                            .offset(#reg_offset as isize) as *mut #reg_ty)
+                    }
+                }
+            });
+
+            // the alternate view may be larger than the register it overlaps
+            // with
+            let end = register.offset + register.size;
+            if end > offset {
+                let name = Ident::new(format!("_reserved{}", i));
+                let pad = (end - offset) as usize;
+                fields.push(quote! {
+                    #name : [u8; #pad]
+                });
+                i += 1;
+                offset = end;
+            }
+
+            continue;
+        }
+
+        let pad = register.offset - offset;
+        if pad != 0 {
+            let name = Ident::new(format!("_reserved{}", i));
+            let pad = pad as usize;
+            fields.push(quote! {
+                #name : [u8; #pad]
+            });
+            i += 1;
+        }
+
+        let alternates = owners.iter()
+            .zip(&registers_)
+            .filter(|&(owner, _)| *owner == Some(j))
+            .map(|(_, r)| format!("`{}()`", r.name))
+            .collect::<Vec<_>>();
+        if !alternates.is_empty() {
+            comment.push_str(&format!("\n\nAlternate views of this \
+                                       register: {}",
+                                      alternates.join(", ")));
+        }
+        let comment = &comment[..];
+
+        fields.push(quote! {
+            #[doc = #comment]
+            pub #reg_name : #reg_ty
+        });
+
+        offset = register.offset + register.size;
+    }
+
+    // the elements of an array of clusters are `dimIncrement` bytes apart
+    if let Some(size) = size {
+        if size > offset {
+            let name = Ident::new(format!("_reserved{}", i));
+            let pad = (size - offset) as usize;
+            fields.push(quote! {
+                #name : [u8; #pad]
+            });
+        }
+    }
+
+    let name = Ident::new(name.to_pascal_case());
+
+    if let Some(description) = description {
+        let comment = &respace(description)[..];
+        items.push(quote! {
+            #[doc = #comment]
+        });
+    }
+
+    let struct_ = quote! {
+        #[repr(C)]
+        pub struct #name {
+            #(#fields),*
+        }
+    };
+
+    items.push(struct_);
+
+    accessors.extend(try!(gen_array_accessors(registers, d)));
+    if !accessors.is_empty() {
+        items.push(quote! {
+            impl #name {
+                #(#accessors)*
+            }
+        });
+    }
+
+    for register in registers {
+        let access = access(&register);
+
+        if opts.generic {
+            items.extend(try!(gen_register_spec(register, d, scope)));
+        } else {
+            items.extend(try!(gen_register_(register, d, opts, scope)));
+        }
+        if let Some(ref fields) = register.fields {
+            items.extend(try!(gen_register_values(register, fields)));
+            if is_readable(access) {
+                items.extend(try!(gen_register_r_(register,
+                                                  d,
+                                                  fields,
+                                                  opts,
+                                                  scope)));
+            }
+            if is_writable(access) {
+                items.extend(try!(gen_register_w_(register,
+                                                  d,
+                                                  fields,
+                                                  opts,
+                                                  scope)));
+            }
+        }
+    }
+
+    for c in &scope.extras.clusters {
+        items.push(try!(gen_cluster(c, d, opts, scope)
+            .map_err(|e| e.within(&c.name))));
+    }
+
+    Ok(items)
+}
+
+/// Generates the module of the cluster `c`, which contains its register block
+/// and the types of its registers; `scope` is the scope of the enclosing
+/// register block
+fn gen_cluster(c: &Cluster,
+               d: &Defaults,
+               opts: &Options,
+               scope: &Scope)
+               -> Result<Tokens> {
+    let root = &scope.root;
+    let name = cluster_name(c);
+    let name_sc = Ident::new(name.to_snake_case().sanitize());
+
+    let items = try!(gen_block(&name,
+                               c.description.as_ref(),
+                               &c.registers,
+                               c.array_info.as_ref().map(|i| i.dim_increment),
+                               d,
+                               opts,
+                               &Scope {
+                                   root: quote! { super::#root },
+                                   extras: &c.extras,
+                               }));
+
+    let comment = &format!("Cluster `{}`", c.name)[..];
+    Ok(quote! {
+        #[doc = #comment]
+        pub mod #name_sc {
+            #(#items)*
+        }
+    })
+}
+
+/// Name of the cluster `c` without the `%s` placeholder of arrays, e.g. `CH`
+/// for `CH%s`
+fn cluster_name(c: &Cluster) -> String {
+    c.name.replace("[%s]", "").replace("%s", "")
+}
+
+/// Returns the size, in bytes, and the alignment of the register block of the
+/// cluster `c`
+///
+/// The register block of an array of clusters is as large as the stride of
+/// the array; it's an error if its registers don't fit in it.
+fn cluster_layout(c: &Cluster, d: &Defaults) -> Result<(u32, u32)> {
+    let (mut end, mut align) = (0, 1);
+
+    for r in &c.registers {
+        let size = try!(storage_size(r, d));
+        let last = match *r {
+            Register::Single(ref info) => info.address_offset,
+            Register::Array(ref info, ref array_info) => {
+                info.address_offset +
+                (array_info.dim - 1) * array_info.dim_increment
+            }
+        };
+        end = cmp::max(end, last + size);
+        align = cmp::max(align, size);
+    }
+
+    for nc in &c.extras.clusters {
+        let (size, nalign) = try!(cluster_layout(nc, d)
+            .map_err(|e| e.within(&nc.name)));
+        let dim = nc.array_info.as_ref().map(|i| i.dim).unwrap_or(1);
+        end = cmp::max(end, nc.address_offset + dim * size);
+        align = cmp::max(align, nalign);
+    }
+
+    // `repr(C)` rounds the size up to the alignment
+    let size = (end + align - 1) / align * align;
+    match c.array_info {
+        Some(ref info) => {
+            if info.dim_increment < size || info.dim_increment % align != 0 {
+                Err(Error::InvalidDim { path: String::new() })
+            } else {
+                Ok((info.dim_increment, align))
+            }
+        }
+        None => Ok((size, align)),
+    }
+}
+
+/// Generates the simulation models of the `registers` of a peripheral, and of
+/// the registers of the clusters in its `extras`, which the `sim` module uses
+fn gen_register_models(registers: &[Register],
+                       extras: &Extras,
+                       d: &Defaults)
+                       -> Result<Tokens> {
+    let mut models = vec![];
+    try!(register_models(registers, extras, 0, d, &mut models));
+
+    Ok(quote! {
+        /// Simulation models of the registers
+        #[cfg(feature = "sim")]
+        pub const MODEL: &'static [super::sim::RegisterModel] = &[
+            #(#models),*
+        ];
+    })
+}
+
+/// Pushes the models of the `registers` of a register block, which starts
+/// `base` bytes past the base address of its peripheral, and of the registers
+/// of its clusters, listed in `extras`, to `models`
+fn register_models(registers: &[Register],
+                   extras: &Extras,
+                   base: u32,
+                   d: &Defaults,
+                   models: &mut Vec<Tokens>)
+                   -> Result<()> {
+    for r in registers {
+        let access = access(r);
+        let size = try!(size_of(r, d));
+
+        let (mut read_mask, mut write_mask) = (0, 0);
+        if let Some(ref fields) = r.fields {
+            for f in fields {
+                let field_mask = mask(f.bit_range.width) << f.bit_range.offset;
+                let field_access = f.access.unwrap_or(access);
+                if is_readable(field_access) {
+                    read_mask |= field_mask;
+                }
+                if is_writable(field_access) {
+                    write_mask |= field_mask;
+                }
+            }
+        } else {
+            read_mask = mask(size);
+            write_mask = mask(size);
+        }
+        if !is_readable(access) {
+            read_mask = 0;
+        }
+        if !is_writable(access) {
+            write_mask = 0;
+        }
+
+        // Only the bits that can be accessed have side effects
+        let effects = side_effect_masks(r, extras, size)
+            .into_iter()
+            .filter_map(|(name, mask)| {
+                let mask = if name.ends_with("_on_read") {
+                    mask & read_mask
+                } else {
+                    mask & write_mask
+                };
+                if mask == 0 {
+                    return None;
+                }
+
+                let (name, mask) = (Ident::new(name), hex64(mask));
+                Some(quote! { #name: #mask, })
+            })
+            .collect::<Vec<_>>();
+
+        let reset_value = reset_value(r, d, extras).unwrap_or(0) &
+                          reset_mask(r, d, extras).unwrap_or(!0) &
+                          mask(size);
+        let (reset_value, read_mask, write_mask) =
+            (hex64(reset_value), hex64(read_mask), hex64(write_mask));
+        let write_once = access == Access::WriteOnce ||
+                         access == Access::ReadWriteOnce;
+
+        let offsets = match *r {
+            Register::Single(ref info) => vec![info.address_offset],
+            Register::Array(ref info, ref array_info) => {
+                (0..array_info.dim)
+                    .map(|i| info.address_offset + i * array_info.dim_increment)
+                    .collect()
+            }
+        };
+
+        for offset in offsets {
+            let offset = hex64((base + offset) as u64);
+            let effects = effects.iter();
+            models.push(quote! {
+                super::sim::RegisterModel {
+                    offset: #offset,
+                    reset_value: #reset_value,
+                    read_mask: #read_mask,
+                    write_mask: #write_mask,
+                    write_once: #write_once,
+                    #(#effects)*
+                    ..super::sim::PLAIN
+                }
+            });
+        }
+    }
+
+    for c in &extras.clusters {
+        let (dim, increment) = c.array_info
+            .as_ref()
+            .map(|i| (i.dim, i.dim_increment))
+            .unwrap_or((1, 0));
+        for i in 0..dim {
+            try!(register_models(&c.registers,
+                                 &c.extras,
+                                 base + c.address_offset + i * increment,
+                                 d,
+                                 models));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the bits of the register `r` whose accesses have side effects,
+/// according to the `modifiedWriteValues` and `readAction` of the register and
+/// of its fields, by the name of the `RegisterModel` field that lists them
+fn side_effect_masks(r: &Register,
+                     extras: &Extras,
+                     size: u32)
+                     -> BTreeMap<&'static str, u64> {
+    let fields = match r.fields {
+        Some(ref fields) => {
+            fields.iter()
+                .map(|f| {
+                    (Some(&f.name[..]),
+                     mask(f.bit_range.width) << f.bit_range.offset)
+                })
+                .collect()
+        }
+        None => vec![(None, mask(size))],
+    };
+
+    let mut masks = BTreeMap::new();
+    for (f, mask) in fields {
+        let write = match extras.semantic(&r.name,
+                                          f,
+                                          |s| s.modified_write_values) {
+            Some(ModifiedWriteValues::OneToClear) => Some("one_to_clear"),
+            Some(ModifiedWriteValues::OneToSet) => Some("one_to_set"),
+            Some(ModifiedWriteValues::OneToToggle) => Some("one_to_toggle"),
+            Some(ModifiedWriteValues::ZeroToClear) => Some("zero_to_clear"),
+            Some(ModifiedWriteValues::ZeroToSet) => Some("zero_to_set"),
+            Some(ModifiedWriteValues::ZeroToToggle) => Some("zero_to_toggle"),
+            Some(ModifiedWriteValues::Clear) => Some("clear_on_write"),
+            Some(ModifiedWriteValues::Set) => Some("set_on_write"),
+            Some(ModifiedWriteValues::Modify) | None => None,
+        };
+        // NOTE the effects of `modify` and `modifyExternal` are not specified
+        let read = match extras.semantic(&r.name, f, |s| s.read_action) {
+            Some(ReadAction::Clear) => Some("clear_on_read"),
+            Some(ReadAction::Set) => Some("set_on_read"),
+            _ => None,
+        };
+
+        for name in write.into_iter().chain(read) {
+            *masks.entry(name).or_insert(0) |= mask;
+        }
+    }
+
+    masks
+}
+/// Generates the methods that index the register arrays of a register block
+///
+/// Register arrays whose elements are contiguous are laid out as Rust arrays;
+/// if their `dimIndex` was given, each element also gets a named accessor.
+/// Register arrays whose elements are *not* contiguous are expanded into
+/// individual registers; these get an accessor that takes the index of the
+/// element.
+fn gen_array_accessors(registers: &[Register],
+                       d: &Defaults)
+                       -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+
+    for r in registers {
+        let (info, array_info) = match *r {
+            Register::Single(_) => continue,
+            Register::Array(ref info, ref array_info) => (info, array_info),
+        };
+
+        let ty = Ident::new(type_of(r));
+        let name = array_name(info);
+        let size = try!(storage_size(info, d));
+
+        if array_info.dim_increment == size {
+            let indices = if let Some(ref indices) = array_info.dim_index {
+                indices
+            } else {
+                continue;
+            };
+
+            let array = Ident::new(&*name);
+            for (idx, i) in indices.iter().zip(0..) {
+                let name = element_name(info, idx);
+                let name_mut = Ident::new(format!("{}_mut", name));
+                let name = Ident::new(name);
+                let i = i as usize;
+                let comment = &format!("0x{:02x} - {}",
+                                       info.address_offset +
+                                       i as u32 * array_info.dim_increment,
+                                       respace(&info.description))[..];
+
+                items.push(quote! {
+                    #[doc = #comment]
+                    pub fn #name(&self) -> &#ty {
+                        &self.#array[#i]
+                    }
+
+                    #[doc = #comment]
+                    pub fn #name_mut(&mut self) -> &mut #ty {
+                        &mut self.#array[#i]
+                    }
+                });
+            }
+        } else {
+            let offset = info.address_offset as usize;
+            let name_mut = Ident::new(format!("{}_mut", name));
+            let name = Ident::new(name);
+            let dim = array_info.dim as usize;
+            let increment = array_info.dim_increment as usize;
+            let comment = &format!("0x{:02x} - {} (`n` in `0..{}`, stride \
+                                    0x{:02x})",
+                                   info.address_offset,
+                                   respace(&info.description),
+                                   dim,
+                                   increment)[..];
+
+            items.push(quote! {
+                #[doc = #comment]
+                pub fn #name(&self, n: usize) -> &#ty {
+                    assert!(n < #dim);
+                    unsafe {
+                        &*((self as *const Self as *const u8)
+                            .offset((#offset + n * #increment) as isize) as
+                           *const #ty)
+                    }
+                }
+
+                #[doc = #comment]
+                pub fn #name_mut(&mut self, n: usize) -> &mut #ty {
+                    assert!(n < #dim);
+                    unsafe {
+                        &mut *((self as *mut Self as *mut u8)
+                            .offset((#offset + n * #increment) as isize) as
+                               *mut #ty)
+                    }
+                }
+            });
+        }
+    }
+
+    Ok(items)
+}
+
+/// Name of the register block field of a register array, e.g. `ccr` for
+/// `CCR%s`
+fn array_name(info: &RegisterInfo) -> String {
+    if info.name.contains("[%s]") {
+        info.name.replace("[%s]", "")
+    } else {
+        info.name.replace("%s", "")
+    }
+        .to_snake_case()
+        .sanitize()
+}
+
+/// Name of the element `idx` of a register array, e.g. `ccr1` for `CCR%s`
+fn element_name(info: &RegisterInfo, idx: &str) -> String {
+    if info.name.contains("[%s]") {
+        info.name.replace("[%s]", idx)
+    } else {
+        info.name.replace("%s", idx)
+    }
+        .to_snake_case()
+        .sanitize()
+}
+
+/// A field of a register block: a register, a register array or a cluster
+struct ExpandedRegister<'a> {
+    description: &'a str,
+    name: String,
+    offset: u32,
+    ty: Either<String, Rc<String>>,
+    /// Size, in bytes, of the register (or of all the elements of the array)
+    size: u32,
+    /// Number of elements, if this is an array laid out as an array
+    dim: Option<u32>,
+}
+
+/// Takes a list of "registers", some of which may actually be register arrays,
+/// and turns it into a new *sorted* (by address offset) list of registers where
+/// the register arrays whose elements are not contiguous have been expanded.
+fn expand<'a>(registers: &'a [Register],
+              d: &Defaults)
+              -> Result<Vec<ExpandedRegister<'a>>> {
+    let mut out = vec![];
+
+    for r in registers {
+        let size = try!(storage_size(r, d));
+
+        match *r {
+            Register::Single(ref info) => {
+                out.push(ExpandedRegister {
+                    description: &info.description,
+                    name: info.name.to_snake_case().sanitize(),
+                    offset: info.address_offset,
+                    ty: Either::Left(info.name.to_pascal_case()),
+                    size: size,
+                    dim: None,
+                })
+            }
+            Register::Array(ref info, ref array_info) => {
+                let ty = Rc::new(type_of(r));
+
+                if array_info.dim_increment == size {
+                    out.push(ExpandedRegister {
+                        description: &info.description,
+                        name: array_name(info),
+                        offset: info.address_offset,
+                        ty: Either::Right(ty),
+                        size: size * array_info.dim,
+                        dim: Some(array_info.dim),
+                    });
+                    continue;
+                }
+
+                let indices = array_info.dim_index
+                    .as_ref()
+                    .map(|v| Cow::from(&**v))
+                    .unwrap_or_else(|| {
+                        Cow::from((0..array_info.dim)
+                            .map(|i| i.to_string())
+                            .collect::<Vec<_>>())
+                    });
+
+                for (idx, i) in indices.iter().zip(0..) {
+                    let offset = info.address_offset +
+                                 i * array_info.dim_increment;
+
+                    out.push(ExpandedRegister {
+                        description: &info.description,
+                        name: element_name(info, idx),
+                        offset: offset,
+                        ty: Either::Right(ty.clone()),
+                        size: size,
+                        dim: None,
+                    });
+                }
+            }
+        }
+    }
+
+    out.sort_by_key(|x| x.offset);
+
+    Ok(out)
+}
+
+/// Turns the `clusters` of a register block into fields of the register
+/// block; an array of clusters is laid out as an array
+fn expand_clusters<'a>(clusters: &'a [Cluster],
+                       d: &Defaults)
+                       -> Result<Vec<ExpandedRegister<'a>>> {
+    let mut out = vec![];
+
+    for c in clusters {
+        let (size, _) = try!(cluster_layout(c, d)
+            .map_err(|e| e.within(&c.name)));
+        let name = cluster_name(c);
+        let dim = c.array_info.as_ref().map(|i| i.dim);
+
+        out.push(ExpandedRegister {
+            description: c.description
+                .as_ref()
+                .map(|d| &d[..])
+                .unwrap_or(&c.name),
+            name: name.to_snake_case().sanitize(),
+            offset: c.address_offset,
+            ty: Either::Left(format!("{}::{}",
+                                     name.to_snake_case().sanitize(),
+                                     name.to_pascal_case())),
+            size: size * dim.unwrap_or(1),
+            dim: dim,
+        });
+    }
+
+    Ok(out)
+}
+
+fn type_of(r: &Register) -> String {
+    let ty = match *r {
+        Register::Single(ref info) => Cow::from(&*info.name),
+        Register::Array(ref info, _) => {
+            if info.name.contains("[%s]") {
+                info.name.replace("[%s]", "").into()
+            } else {
+                info.name.replace("%s", "").into()
+            }
+        }
+    };
+
+    (&*ty).to_pascal_case()
+}
+
+fn access(r: &Register) -> Access {
+    r.access.unwrap_or_else(|| {
+        if let Some(ref fields) = r.fields {
+            if fields.iter().all(|f| f.access == Some(Access::ReadOnly)) {
+                Access::ReadOnly
+            } else if fields.iter().all(|f| f.access == Some(Access::WriteOnly)) {
+                Access::WriteOnly
+            } else {
+                Access::ReadWrite
+            }
+        } else {
+            Access::ReadWrite
+        }
+    })
+}
+
+/// Can registers (or fields) with this `access` be read?
+fn is_readable(access: Access) -> bool {
+    match access {
+        Access::ReadOnly | Access::ReadWrite | Access::ReadWriteOnce => true,
+        Access::WriteOnly | Access::WriteOnce => false,
+    }
+}
+
+/// Can registers (or fields) with this `access` be written?
+fn is_writable(access: Access) -> bool {
+    access != Access::ReadOnly
+}
+
+/// Generates `VolatileCell`, the register type of the `shared`,
+/// `self_contained` and `bus` modes
+fn gen_volatile_cell(opts: &Options) -> Tokens {
+    if opts.bus {
+        return quote! {
+            /// Memory location that is read and written through `Bus`
+            ///
+            /// It can be written through a shared reference; it's not `Sync`.
+            #[repr(C)]
+            struct VolatileCell<T> {
+                value: ::core::cell::UnsafeCell<T>,
+            }
+
+            impl<T: Bits> VolatileCell<T> {
+                /// Reads the value
+                pub fn read(&self) -> T {
+                    let address = self.value.get() as usize;
+                    let bits = <Bus as RegisterBus>::read(address, T::width());
+                    T::from_bits(bits)
+                }
+
+                /// Writes `value`
+                pub fn write(&self, value: T) {
+                    let address = self.value.get() as usize;
+                    <Bus as RegisterBus>::write(address,
+                                                T::width(),
+                                                value.into_bits())
+                }
+            }
+        };
+    }
+
+    quote! {
+        /// Memory location that is read and written using volatile operations
+        ///
+        /// Unlike the types of `volatile_register`, it can be written through
+        /// a shared reference; it's not `Sync`.
+        #[repr(C)]
+        struct VolatileCell<T> {
+            value: ::core::cell::UnsafeCell<T>,
+        }
+
+        impl<T: Copy> VolatileCell<T> {
+            /// Reads the value
+            pub fn read(&self) -> T {
+                unsafe { ::core::ptr::read_volatile(self.value.get()) }
+            }
+
+            /// Writes `value`
+            pub fn write(&self, value: T) {
+                unsafe { ::core::ptr::write_volatile(self.value.get(), value) }
+            }
+        }
+    }
+}
+
+/// Generates the `RegisterBus` trait, its volatile implementation and the
+/// mock one, which is selected by the `mock` feature of the generated crate
+fn gen_bus() -> Tokens {
+    let mut items = vec![];
+
+    items.push(quote! {
+        /// Bus through which the registers are read and written
+        pub trait RegisterBus {
+            /// Reads the register at `address`, which is `width` bits wide
+            fn read(address: usize, width: u8) -> u64;
+
+            /// Writes `value` to the register at `address`, which is `width`
+            /// bits wide
+            fn write(address: usize, width: u8, value: u64);
+        }
+
+        /// Integer that stores the value of a register
+        pub trait Bits: Copy {
+            /// Width of the integer, in bits
+            fn width() -> u8;
+
+            /// Truncates `bits`
+            fn from_bits(bits: u64) -> Self;
+
+            /// Zero extends the integer
+            fn into_bits(self) -> u64;
+        }
+    });
+
+    for &(ty, width) in &[("u8", 8u8), ("u16", 16), ("u32", 32), ("u64", 64)] {
+        let ty = Ident::new(ty);
+        items.push(quote! {
+            impl Bits for #ty {
+                fn width() -> u8 {
+                    #width
+                }
+
+                fn from_bits(bits: u64) -> Self {
+                    bits as #ty
+                }
+
+                fn into_bits(self) -> u64 {
+                    self as u64
+                }
+            }
+        });
+    }
+
+    items.push(quote! {
+        /// Bus that accesses the registers using volatile operations
+        pub struct VolatileBus;
+
+        impl RegisterBus for VolatileBus {
+            #[inline(always)]
+            fn read(address: usize, width: u8) -> u64 {
+                use core::ptr::read_volatile;
+
+                unsafe {
+                    match width {
+                        8 => read_volatile(address as *const u8) as u64,
+                        16 => read_volatile(address as *const u16) as u64,
+                        32 => read_volatile(address as *const u32) as u64,
+                        _ => read_volatile(address as *const u64),
+                    }
+                }
+            }
+
+            #[inline(always)]
+            fn write(address: usize, width: u8, value: u64) {
+                use core::ptr::write_volatile;
+
+                unsafe {
+                    match width {
+                        8 => write_volatile(address as *mut u8, value as u8),
+                        16 => write_volatile(address as *mut u16, value as u16),
+                        32 => write_volatile(address as *mut u32, value as u32),
+                        _ => write_volatile(address as *mut u64, value),
+                    }
+                }
+            }
+        }
+
+        /// Bus that the registers use
+        #[cfg(not(any(feature = "mock", feature = "sim")))]
+        pub type Bus = VolatileBus;
+
+        /// Bus that the registers use
+        #[cfg(feature = "mock")]
+        pub type Bus = mock::MockBus;
+
+        /// Bus that the registers use
+        #[cfg(all(feature = "sim", not(feature = "mock")))]
+        pub type Bus = sim::SimBus;
+    });
+
+    items.push(quote! {
+        /// Returns a pointer to the register block at `address`
+        #[cfg(not(any(feature = "mock", feature = "sim")))]
+        #[inline(always)]
+        fn register_block<T>(address: usize) -> *const T {
+            address as *const T
+        }
+
+        /// Returns a pointer to the memory that stands in for the register
+        /// block at `address`
+        #[cfg(any(feature = "mock", feature = "sim"))]
+        fn register_block<T>(address: usize) -> *const T {
+            host::block(address, ::core::mem::size_of::<T>()) as *const T
+        }
+    });
+
+    items.push(quote! {
+        /// Host memory that stands in for the register blocks
+        ///
+        /// The register blocks can't be referenced at their physical address
+        /// on the host. The memory is allocated on first use and never freed,
+        /// and the registers never read or write it: the bus translates its
+        /// addresses back to physical ones. The blocks are per thread.
+        #[cfg(any(feature = "mock", feature = "sim"))]
+        mod host {
+            use std::cell::RefCell;
+            use std::vec::Vec;
+
+            thread_local! {
+                // (host address, size, physical address) of each block
+                static BLOCKS: RefCell<Vec<(usize, usize, usize)>> =
+                    RefCell::new(Vec::new());
+            }
+
+            /// Returns the host memory that stands in for the `size` bytes of
+            /// registers at the physical `address`
+            pub fn block(address: usize, size: usize) -> *const u8 {
+                BLOCKS.with(|blocks| {
+                    let blocks = &mut *blocks.borrow_mut();
+                    if let Some(&(host, _, _)) = blocks.iter()
+                        .find(|&&(_, s, a)| a == address && s >= size) {
+                        return host as *const u8;
+                    }
+
+                    // NOTE `u64`s so the registers are aligned
+                    let memory = vec![0u64; size / 8 + 1];
+                    let host = memory.as_ptr() as usize;
+                    ::std::mem::forget(memory);
+                    blocks.push((host, size, address));
+                    host as *const u8
+                })
+            }
+
+            /// Translates the host `address` of a register into its physical
+            /// address
+            pub fn physical(address: usize) -> usize {
+                BLOCKS.with(|blocks| {
+                    blocks.borrow()
+                        .iter()
+                        .find(|&&(host, size, _)| {
+                            address >= host && address < host + size
+                        })
+                        .map(|&(host, _, physical)| {
+                            physical + (address - host)
+                        })
+                        .unwrap_or(address)
+                })
+            }
+        }
+    });
+
+    let mut mock = vec![];
+
+    mock.push(quote! {
+            use std::cell::RefCell;
+            use std::collections::{HashMap, VecDeque};
+            use std::vec::Vec;
+
+            /// Register access recorded by `MockBus`
+            #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+            pub enum Access {
+                /// `value` was read from the register at `address`
+                Read { address: usize, width: u8, value: u64 },
+                /// `value` was written to the register at `address`
+                Write { address: usize, width: u8, value: u64 },
+            }
+
+            struct State {
+                accesses: Vec<Access>,
+                memory: HashMap<usize, u64>,
+                reads: HashMap<usize, VecDeque<u64>>,
+            }
+
+            thread_local! {
+                static STATE: RefCell<State> = RefCell::new(State {
+                    accesses: Vec::new(),
+                    memory: HashMap::new(),
+                    reads: HashMap::new(),
+                });
+            }
+
+            /// Bus that records the register accesses
+            pub struct MockBus;
+
+            impl super::RegisterBus for MockBus {
+                fn read(address: usize, width: u8) -> u64 {
+                    let address = super::host::physical(address);
+                    STATE.with(|state| {
+                        let state = &mut *state.borrow_mut();
+                        let value = state.reads
+                            .get_mut(&address)
+                            .and_then(|values| values.pop_front())
+                            .or_else(|| state.memory.get(&address).cloned())
+                            .unwrap_or(0);
+                        state.accesses.push(Access::Read {
+                            address: address,
+                            width: width,
+                            value: value,
+                        });
+                        value
+                    })
+                }
+
+                fn write(address: usize, width: u8, value: u64) {
+                    let address = super::host::physical(address);
+                    STATE.with(|state| {
+                        let state = &mut *state.borrow_mut();
+                        state.memory.insert(address, value);
+                        state.accesses.push(Access::Write {
+                            address: address,
+                            width: width,
+                            value: value,
+                        });
+                    })
+                }
+            }
+    });
+
+    mock.push(quote! {
+            /// Queues `value` as the result of the next read of the register
+            /// at `address`
+            pub fn push_read(address: usize, value: u64) {
+                STATE.with(|state| {
+                    state.borrow_mut()
+                        .reads
+                        .entry(address)
+                        .or_insert_with(VecDeque::new)
+                        .push_back(value)
+                })
+            }
+
+            /// Returns the accesses recorded since the last call, and clears
+            /// them
+            pub fn take_accesses() -> Vec<Access> {
+                STATE.with(|state| {
+                    ::std::mem::replace(&mut state.borrow_mut().accesses,
+                                        Vec::new())
+                })
+            }
+
+            /// Clears the recorded accesses, the contents of the registers and
+            /// the queued reads
+            pub fn reset() {
+                STATE.with(|state| {
+                    let state = &mut *state.borrow_mut();
+                    state.accesses.clear();
+                    state.memory.clear();
+                    state.reads.clear();
+                })
+            }
+    });
+
+    items.push(quote! {
+        /// In-memory bus that records the register accesses
+        ///
+        /// Reads return the values queued with `push_read`, if any, or else
+        /// the value last written to the register, or else zero. The state of
+        /// the mock is per thread.
+        #[cfg(feature = "mock")]
+        pub mod mock {
+            #(#mock)*
+        }
+    });
+
+    quote! {
+        #(#items)*
+    }
+}
+
+/// Generates the `sim` module, which simulates the `peripherals` according to
+/// the models generated by `gen_register_models`
+fn gen_sim(peripherals: &[Peripheral]) -> Tokens {
+    let mut items = vec![];
+
+    let models = peripherals.iter()
+        .filter(|p| p.registers.is_some())
+        .map(|p| {
+            let name = &p.name[..];
+            let name_sc = Ident::new(p.name.to_snake_case().sanitize());
+            let base_address = hex(p.base_address);
+            quote! {
+                PeripheralModel {
+                    name: #name,
+                    base_address: #base_address,
+                    registers: super::#name_sc::MODEL,
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    items.push(quote! {
+        use std::cell::RefCell;
+        use std::collections::{HashMap, HashSet};
+
+        /// Behavior of a register, as described by the SVD file
+        pub struct RegisterModel {
+            /// Offset of the register from the base address of its peripheral
+            pub offset: usize,
+            /// Value of the register after a reset
+            pub reset_value: u64,
+            /// Bits that can be read; the others, e.g. the reserved bits, read
+            /// as zero
+            pub read_mask: u64,
+            /// Bits that can be written; writes to the others are ignored
+            pub write_mask: u64,
+            /// Only the first write after a reset has an effect
+            pub write_once: bool,
+            /// Writable bits that writing a one clears
+            pub one_to_clear: u64,
+            /// Writable bits that writing a one sets
+            pub one_to_set: u64,
+            /// Writable bits that writing a one toggles
+            pub one_to_toggle: u64,
+            /// Writable bits that writing a zero clears
+            pub zero_to_clear: u64,
+            /// Writable bits that writing a zero sets
+            pub zero_to_set: u64,
+            /// Writable bits that writing a zero toggles
+            pub zero_to_toggle: u64,
+            /// Writable bits that any write clears
+            pub clear_on_write: u64,
+            /// Writable bits that any write sets
+            pub set_on_write: u64,
+            /// Readable bits that reading clears
+            pub clear_on_read: u64,
+            /// Readable bits that reading sets
+            pub set_on_read: u64,
+        }
+
+        /// Model of a register that has no bits; the models of the registers
+        /// of the device only list the side effects that differ from it
+        pub const PLAIN: RegisterModel = RegisterModel {
+            offset: 0,
+            reset_value: 0,
+            read_mask: 0,
+            write_mask: 0,
+            write_once: false,
+            one_to_clear: 0,
+            one_to_set: 0,
+            one_to_toggle: 0,
+            zero_to_clear: 0,
+            zero_to_set: 0,
+            zero_to_toggle: 0,
+            clear_on_write: 0,
+            set_on_write: 0,
+            clear_on_read: 0,
+            set_on_read: 0,
+        };
+
+        /// Model of the addresses that have no register, e.g. the gaps
+        /// between registers; they behave as plain memory that resets to zero
+        static MEMORY: RegisterModel = RegisterModel {
+            read_mask: !0,
+            write_mask: !0,
+            ..PLAIN
+        };
+
+        /// Registers of a peripheral
+        pub struct PeripheralModel {
+            pub name: &'static str,
+            pub base_address: usize,
+            pub registers: &'static [RegisterModel],
+        }
+
+        /// Peripherals of the device
+        pub static PERIPHERALS: &'static [PeripheralModel] = &[
+            #(#models),*
+        ];
+
+        struct State {
+            values: HashMap<usize, u64>,
+            written: HashSet<usize>,
+        }
+
+        thread_local! {
+            static STATE: RefCell<State> = RefCell::new(State {
+                values: HashMap::new(),
+                written: HashSet::new(),
+            });
+        }
+
+        /// Returns the model of the register at `address`, or `MEMORY` if
+        /// there's no register at that address
+        fn model(address: usize) -> &'static RegisterModel {
+            for p in PERIPHERALS {
+                for r in p.registers {
+                    if p.base_address + r.offset == address {
+                        return r;
+                    }
+                }
+            }
+
+            &MEMORY
+        }
+    });
+
+    items.push(quote! {
+        /// Bus that simulates the registers
+        pub struct SimBus;
+
+        impl super::RegisterBus for SimBus {
+            fn read(address: usize, _width: u8) -> u64 {
+                let address = super::host::physical(address);
+                let r = model(address);
+                let old = peek(address);
+                if r.clear_on_read | r.set_on_read != 0 {
+                    STATE.with(|state| {
+                        state.borrow_mut()
+                            .values
+                            .insert(address,
+                                    old & !r.clear_on_read | r.set_on_read);
+                    })
+                }
+                old & r.read_mask
+            }
+
+            fn write(address: usize, _width: u8, value: u64) {
+                let address = super::host::physical(address);
+                let r = model(address);
+                STATE.with(|state| {
+                    let state = &mut *state.borrow_mut();
+                    if r.write_once && !state.written.insert(address) {
+                        return;
+                    }
+
+                    let old = state.values
+                        .get(&address)
+                        .cloned()
+                        .unwrap_or(r.reset_value);
+                    state.values.insert(address, written(r, old, value));
+                })
+            }
+        }
+
+        /// Returns the value of the register `r` after writing `value` to it
+        /// when its value was `old`
+        fn written(r: &RegisterModel, old: u64, value: u64) -> u64 {
+            let stored = r.write_mask &
+                         !(r.one_to_clear | r.one_to_set | r.one_to_toggle |
+                           r.zero_to_clear | r.zero_to_set |
+                           r.zero_to_toggle | r.clear_on_write |
+                           r.set_on_write);
+
+            old & !r.write_mask | value & stored |
+            old & !value & r.one_to_clear |
+            (old | value) & r.one_to_set |
+            (old ^ value) & r.one_to_toggle |
+            old & value & r.zero_to_clear |
+            (old | !value) & r.zero_to_set |
+            (old ^ !value) & r.zero_to_toggle |
+            r.set_on_write
+        }
+
+        /// Returns the value of the register at `address`, including the bits
+        /// that can't be read
+        pub fn peek(address: usize) -> u64 {
+            let r = model(address);
+            STATE.with(|state| {
+                state.borrow()
+                    .values
+                    .get(&address)
+                    .cloned()
+                    .unwrap_or(r.reset_value)
+            })
+        }
+
+        /// Sets the value of the register at `address` like the hardware
+        /// would, e.g. to raise a status flag; the access rules don't apply
+        pub fn poke(address: usize, value: u64) {
+            STATE.with(|state| {
+                state.borrow_mut().values.insert(address, value);
+            })
+        }
+
+        /// Resets all the registers to their reset value
+        pub fn reset() {
+            STATE.with(|state| {
+                let state = &mut *state.borrow_mut();
+                state.values.clear();
+                state.written.clear();
+            })
+        }
+    });
+
+    quote! {
+        /// Simulation of the peripherals
+        ///
+        /// The registers start with their reset value; the bits that are not
+        /// readable read as zero and writes to the bits that are not writable
+        /// are ignored. The bits whose `modifiedWriteValues` or `readAction`
+        /// give accesses a side effect, e.g. write-1-to-clear and
+        /// clear-on-read flags, behave like the hardware. The addresses that
+        /// have no register behave as plain memory. The state of the
+        /// simulation is per thread.
+        #[cfg(feature = "sim")]
+        pub mod sim {
+            #(#items)*
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn gen_register(r: &Register,
+                    d: &Defaults,
+                    opts: &Options)
+                    -> Result<Vec<Tokens>> {
+    gen_register_(r, d, opts, &Scope::peripheral(&Extras::default()))
+}
+
+fn gen_register_(r: &Register,
+                 d: &Defaults,
+                 opts: &Options,
+                 scope: &Scope)
+                 -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+    let extras = scope.extras;
+
+    let ty = type_of(r);
+    let name = Ident::new(&*ty);
+    let bits_ty = try!(bits_ty(r, d));
+    let access = access(r);
+
+    // Registers whose size is not a power of two are stored in the next
+    // larger integer; the bits that don't belong to the register are masked
+    let size = try!(size_of(r, d));
+    let (read, write_bits, write_w, write_value) = if size.is_power_of_two() {
+        (quote! { self.register.read() },
+         quote! { self.register.write(bits); },
+         quote! { self.register.write(w.bits); },
+         quote! { self.register.write(value); })
+    } else {
+        let mask = Lit::Int(mask(size), IntTy::Unsuffixed);
+        (quote! { self.register.read() & #mask },
+         quote! { self.register.write(bits & #mask); },
+         quote! { self.register.write(w.bits & #mask); },
+         quote! { self.register.write(value & #mask); })
+    };
+
+    // In the `shared` mode the methods that write to the register take `&self`
+    let self_ = if opts.shared {
+        quote! { &self }
+    } else {
+        quote! { &mut self }
+    };
+
+    let register_ty = if let Some(path) = opts.volatile_path() {
+        let ty = path_tokens(path);
+        match access {
+            Access::ReadOnly => quote! { #ty::RO<#bits_ty> },
+            Access::ReadWrite | Access::ReadWriteOnce => {
+                quote! { #ty::RW<#bits_ty> }
+            }
+            Access::WriteOnly | Access::WriteOnce => {
+                quote! { #ty::WO<#bits_ty> }
+            }
+        }
+    } else {
+        let root = &scope.root;
+        quote! { #root::VolatileCell<#bits_ty> }
+    };
+
+    items.push(quote! {
+        #[repr(C)]
+        pub struct #name {
+            register: #register_ty
+        }
+    });
+
+    // Writes to the `writeOnce` and `read-writeOnce` registers other than the
+    // first one after a reset are ignored by the hardware. These registers
+    // have no `modify` method and their `write` method is named `write_once`.
+    let write_once_doc = "Writes to the register\n\n**NOTE** This register \
+                          can only be written once after a reset; the \
+                          hardware ignores any subsequent write.";
+
+    // The reads of the registers that have a `readAction` have side effects
+    // so their readers have a distinct name and they have no `modify` method
+    let side_effects = read_side_effects(r, extras);
+    let (read_fn, read_bits_fn, read_doc) = match side_effects {
+        Some(ref doc) => {
+            let doc = &doc[..];
+            (Ident::new("read_with_side_effects"),
+             Ident::new("read_bits_with_side_effects"),
+             quote! { #[doc = #doc] })
+        }
+        None => (Ident::new("read"), Ident::new("read_bits"), quote! {}),
+    };
+
+    if r.fields.is_some() {
+        let name_r = Ident::new(format!("{}R", ty));
+        let name_w = Ident::new(format!("{}W", ty));
+
+        // The fields whose writes have side effects, e.g. `oneToClear` flags,
+        // start with the value that leaves them unchanged
+        let (zeros, ones) = neutral_bits(r, extras);
+        let (modified, initial) = if zeros | ones == 0 {
+            (quote! { bits }, quote! { #name_w::reset_value() })
+        } else {
+            let reset_value = reset_value(r, d, extras).unwrap_or(0);
+            let initial = Lit::Int((reset_value & !zeros | ones) & mask(size),
+                                   IntTy::Unsuffixed);
+            let (zeros, ones) = (Lit::Int(zeros, IntTy::Unsuffixed),
+                                 Lit::Int(ones, IntTy::Unsuffixed));
+            (quote! { bits & !#zeros | #ones },
+             quote! { #name_w { bits: #initial } })
+        };
+
+        let (modify_bits, modify) = if side_effects.is_some() {
+            (quote! {}, quote! {})
+        } else {
+            (quote! {
+                pub unsafe fn modify_bits<F>(#self_, f: F)
+                    where F: FnOnce(&mut #bits_ty)
+                {
+                    let mut bits = #read;
+                    f(&mut bits);
+                    #write_bits
+                }
+            },
+             quote! {
+                pub fn modify<F>(#self_, f: F)
+                    where for<'w> F: FnOnce(&#name_r, &'w mut #name_w) -> &'w mut #name_w,
+                {
+                    let bits = #read;
+                    let r = #name_r { bits: bits };
+                    let mut w = #name_w { bits: #modified };
+                    f(&r, &mut w);
+                    #write_w
+                }
+            })
+        };
+
+        match access {
+            Access::ReadOnly => {
+                items.push(quote! {
+                    impl #name {
+                        #read_doc
+                        pub fn #read_bits_fn(&self) -> #bits_ty {
+                            #read
+                        }
+
+                        #read_doc
+                        pub fn #read_fn(&self) -> #name_r {
+                            #name_r { bits: #read }
+                        }
+                    }
+                });
+            }
+            Access::ReadWrite => {
+                items.push(quote! {
+                    impl #name {
+                        #read_doc
+                        pub fn #read_bits_fn(&self) -> #bits_ty {
+                            #read
+                        }
+
+                        #modify_bits
+
+                        pub unsafe fn write_bits(#self_, bits: #bits_ty) {
+                            #write_bits
+                        }
+
+                        #modify
+
+                        #read_doc
+                        pub fn #read_fn(&self) -> #name_r {
+                            #name_r { bits: #read }
+                        }
+
+                        pub fn write<F>(#self_, f: F)
+                            where F: FnOnce(&mut #name_w) -> &mut #name_w,
+                        {
+                            let mut w = #initial;
+                            f(&mut w);
+                            #write_w
+                        }
+                    }
+                });
+            }
+
+            Access::WriteOnly => {
+                items.push(quote! {
+                    impl #name {
+                        pub unsafe fn write_bits(#self_, bits: #bits_ty) {
+                            #write_bits
+                        }
+
+                        pub fn write<F>(&self, f: F)
+                            where F: FnOnce(&mut #name_w) -> &mut #name_w,
+                        {
+                            let mut w = #initial;
+                            f(&mut w);
+                            #write_w
+                        }
+                    }
+                });
+            }
+
+            Access::ReadWriteOnce => {
+                items.push(quote! {
+                    impl #name {
+                        #read_doc
+                        pub fn #read_bits_fn(&self) -> #bits_ty {
+                            #read
+                        }
+
+                        #read_doc
+                        pub fn #read_fn(&self) -> #name_r {
+                            #name_r { bits: #read }
+                        }
+
+                        pub unsafe fn write_bits(#self_, bits: #bits_ty) {
+                            #write_bits
+                        }
+
+                        #[doc = #write_once_doc]
+                        pub fn write_once<F>(#self_, f: F)
+                            where F: FnOnce(&mut #name_w) -> &mut #name_w,
+                        {
+                            let mut w = #initial;
+                            f(&mut w);
+                            #write_w
+                        }
+                    }
+                });
+            }
+
+            Access::WriteOnce => {
+                items.push(quote! {
+                    impl #name {
+                        pub unsafe fn write_bits(#self_, bits: #bits_ty) {
+                            #write_bits
+                        }
+
+                        #[doc = #write_once_doc]
+                        pub fn write_once<F>(#self_, f: F)
+                            where F: FnOnce(&mut #name_w) -> &mut #name_w,
+                        {
+                            let mut w = #initial;
+                            f(&mut w);
+                            #write_w
+                        }
+                    }
+                });
+            }
+        }
+    } else {
+        match access {
+            Access::ReadOnly => {
+                items.push(quote! {
+                    impl #name {
+                        #read_doc
+                        pub fn #read_fn(&self) -> #bits_ty {
+                            #read
+                        }
+                    }
+                });
+            }
+            Access::ReadWrite => {
+                items.push(quote! {
+                    impl #name {
+                        #read_doc
+                        pub fn #read_fn(&self) -> #bits_ty {
+                            #read
+                        }
+
+                        pub fn write(#self_, value: #bits_ty) {
+                            #write_value
+                        }
+                    }
+                });
+            }
+
+            Access::WriteOnly => {
+                items.push(quote! {
+                    impl #name {
+                        pub fn write(#self_, value: #bits_ty) {
+                            #write_value
+                        }
+                    }
+                });
+            }
+
+            Access::ReadWriteOnce => {
+                items.push(quote! {
+                    impl #name {
+                        #read_doc
+                        pub fn #read_fn(&self) -> #bits_ty {
+                            #read
+                        }
+
+                        #[doc = #write_once_doc]
+                        pub fn write_once(#self_, value: #bits_ty) {
+                            #write_value
+                        }
+                    }
+                });
+            }
+
+            Access::WriteOnce => {
+                items.push(quote! {
+                    impl #name {
+                        #[doc = #write_once_doc]
+                        pub fn write_once(#self_, value: #bits_ty) {
+                            #write_value
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// Generates the generic register types and the traits that the register
+/// specifications implement
+///
+/// Registers that have fields are `Reg`s, which are read and written through
+/// the generic `R` and `W` proxies; registers that don't are `RawReg`s.
+fn gen_generic(opts: &Options) -> Tokens {
+    let mut items = vec![];
+
+    let cell = if let Some(path) = opts.volatile_path() {
+        let cell = path_tokens(path);
+        quote! { #cell::RW }
+    } else {
+        quote! { VolatileCell }
+    };
+    let self_ = if opts.shared {
+        quote! { &self }
+    } else {
+        quote! { &mut self }
+    };
+    // `VolatileCell` reads and writes `Bits` in the `bus` mode
+    let bits = if opts.bus {
+        quote! { + Bits }
+    } else {
+        quote! {}
+    };
+
+    items.push(quote! {
+        /// Specification of a register
+        pub trait RegisterSpec {
+            /// Integer that stores the value of the register
+            type Ux: Copy + Default +
+                ::core::ops::BitAnd<Output = Self::Ux> +
+                ::core::ops::BitOr<Output = Self::Ux> +
+                ::core::ops::Not<Output = Self::Ux> #bits;
+
+            /// Returns the bits of `Ux` that belong to the register
+            fn mask() -> Self::Ux;
+
+            /// Returns the reset value of the register
+            fn reset_value() -> Self::Ux;
+
+            /// Returns the bits that must be written as zeros to leave the
+            /// fields whose writes have side effects, e.g. `oneToClear`
+            /// flags, unchanged
+            fn neutral_zeros() -> Self::Ux {
+                Default::default()
+            }
+
+            /// Returns the bits that must be written as ones to leave the
+            /// fields whose writes have side effects, e.g. `zeroToClear`
+            /// flags, unchanged
+            fn neutral_ones() -> Self::Ux {
+                Default::default()
+            }
+        }
+
+        /// Register that can be read
+        pub trait Readable: RegisterSpec {}
+
+        /// Register whose reads have side effects, e.g. clearing flags, as
+        /// described by its `readAction`
+        pub trait ReadableWithSideEffects: RegisterSpec {}
+
+        /// Register that can be written
+        pub trait Writable: RegisterSpec {}
+
+        /// Register that can be written any number of times
+        pub trait Rewritable: Writable {}
+
+        /// Register that can only be written once after a reset
+        pub trait WriteOnce: Writable {}
+
+    });
+
+    items.push(quote! {
+        /// Value read from a register
+        ///
+        /// The reader of each field is an inherent method of `R<SPEC>`.
+        #[repr(C)]
+        pub struct R<SPEC: RegisterSpec> {
+            bits: SPEC::Ux,
+        }
+
+        impl<SPEC: RegisterSpec> Clone for R<SPEC> {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl<SPEC: RegisterSpec> Copy for R<SPEC> {}
+
+        /// Value to write to a register
+        ///
+        /// The writer of each field is an inherent method of `W<SPEC>`.
+        #[repr(C)]
+        pub struct W<SPEC: RegisterSpec> {
+            bits: SPEC::Ux,
+        }
+
+        impl<SPEC: RegisterSpec> Clone for W<SPEC> {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl<SPEC: RegisterSpec> Copy for W<SPEC> {}
+
+        impl<SPEC: RegisterSpec> W<SPEC> {
+            /// Reset value
+            pub fn reset_value() -> Self {
+                W { bits: SPEC::reset_value() }
+            }
+
+            /// Returns a writer that starts from `bits` where the fields
+            /// whose writes have side effects hold their neutral value
+            fn neutral(bits: SPEC::Ux) -> Self {
+                W { bits: bits & !SPEC::neutral_zeros() | SPEC::neutral_ones() }
+            }
+        }
+    });
+
+    items.push(quote! {
+        /// Register that has fields
+        #[repr(C)]
+        pub struct Reg<SPEC: RegisterSpec> {
+            register: #cell<SPEC::Ux>,
+        }
+
+        impl<SPEC: Readable> Reg<SPEC> {
+            /// Reads the raw bits of the register
+            pub fn read_bits(&self) -> SPEC::Ux {
+                self.register.read() & SPEC::mask()
+            }
+        }
+
+        impl<SPEC: Readable> Reg<SPEC> {
+            /// Reads the register
+            pub fn read(&self) -> R<SPEC> {
+                R { bits: self.read_bits() }
+            }
+        }
+
+        impl<SPEC: ReadableWithSideEffects> Reg<SPEC> {
+            /// Reads the raw bits of the register
+            ///
+            /// **NOTE** Reading this register has side effects.
+            pub fn read_bits_with_side_effects(&self) -> SPEC::Ux {
+                self.register.read() & SPEC::mask()
+            }
+
+            /// Reads the register
+            ///
+            /// **NOTE** Reading this register has side effects.
+            pub fn read_with_side_effects(&self) -> R<SPEC> {
+                R { bits: self.read_bits_with_side_effects() }
+            }
+        }
+
+        impl<SPEC: Writable> Reg<SPEC> {
+            /// Writes raw bits to the register
+            pub unsafe fn write_bits(#self_, bits: SPEC::Ux) {
+                self.register.write(bits & SPEC::mask());
+            }
+        }
+
+        impl<SPEC: Readable + Rewritable> Reg<SPEC> {
+            /// Modifies the raw bits of the register
+            pub unsafe fn modify_bits<F>(#self_, f: F)
+                where F: FnOnce(&mut SPEC::Ux)
+            {
+                let mut bits = self.read_bits();
+                f(&mut bits);
+                self.write_bits(bits);
+            }
+        }
+
+    });
+
+    items.push(quote! {
+        impl<SPEC: Rewritable> Reg<SPEC> {
+            /// Writes to the register; the fields that `f` doesn't write are
+            /// set to their reset value
+            pub fn write<F>(#self_, f: F)
+                where F: FnOnce(&mut W<SPEC>) -> &mut W<SPEC>
+            {
+                let mut w = W::neutral(SPEC::reset_value());
+                f(&mut w);
+                unsafe { self.write_bits(w.bits) }
+            }
+        }
+
+        impl<SPEC: Readable + Rewritable> Reg<SPEC> {
+            /// Modifies the register; the fields that `f` doesn't write keep
+            /// their value
+            pub fn modify<F>(#self_, f: F)
+                where for<'w> F: FnOnce(&R<SPEC>, &'w mut W<SPEC>)
+                                        -> &'w mut W<SPEC>
+            {
+                let bits = self.read_bits();
+                let r = R { bits: bits };
+                let mut w = W::neutral(bits);
+                f(&r, &mut w);
+                unsafe { self.write_bits(w.bits) }
+            }
+        }
+
+        impl<SPEC: WriteOnce> Reg<SPEC> {
+            /// Writes to the register
+            ///
+            /// **NOTE** This register can only be written once after a
+            /// reset; the hardware ignores any subsequent write.
+            pub fn write_once<F>(#self_, f: F)
+                where F: FnOnce(&mut W<SPEC>) -> &mut W<SPEC>
+            {
+                let mut w = W::neutral(SPEC::reset_value());
+                f(&mut w);
+                unsafe { self.write_bits(w.bits) }
+            }
+        }
+
+    });
+
+    items.push(quote! {
+        /// Register that has no fields
+        #[repr(C)]
+        pub struct RawReg<SPEC: RegisterSpec> {
+            register: #cell<SPEC::Ux>,
+        }
+
+        impl<SPEC: Readable> RawReg<SPEC> {
+            /// Reads the register
+            pub fn read(&self) -> SPEC::Ux {
+                self.register.read() & SPEC::mask()
+            }
+        }
+
+        impl<SPEC: ReadableWithSideEffects> RawReg<SPEC> {
+            /// Reads the register
+            ///
+            /// **NOTE** Reading this register has side effects.
+            pub fn read_with_side_effects(&self) -> SPEC::Ux {
+                self.register.read() & SPEC::mask()
+            }
+        }
+
+        impl<SPEC: Rewritable> RawReg<SPEC> {
+            /// Writes `value` to the register
+            pub fn write(#self_, value: SPEC::Ux) {
+                self.register.write(value & SPEC::mask());
+            }
+        }
+
+        impl<SPEC: WriteOnce> RawReg<SPEC> {
+            /// Writes `value` to the register
+            ///
+            /// **NOTE** This register can only be written once after a
+            /// reset; the hardware ignores any subsequent write.
+            pub fn write_once(#self_, value: SPEC::Ux) {
+                self.register.write(value & SPEC::mask());
+            }
+        }
+    });
+
+    quote! {
+        #(#items)*
+    }
+}
+
+/// Generates the specification of a register plus the alias of its type, for
+/// the generic register types generated by `gen_generic`
+fn gen_register_spec(r: &Register,
+                     d: &Defaults,
+                     scope: &Scope)
+                     -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+    let root = &scope.root;
+
+    let ty = type_of(r);
+    let name = Ident::new(&*ty);
+    let spec = Ident::new(format!("{}Spec", ty));
+    let bits_ty = try!(bits_ty(r, d));
+    let access = access(r);
+
+    let size = try!(size_of(r, d));
+    let mask = Lit::Int(mask(size), IntTy::Unsuffixed);
+    let reset_value = Lit::Int(reset_value(r, d, scope.extras).unwrap_or(0),
+                               IntTy::Unsuffixed);
+
+    let (zeros, ones) = neutral_bits(r, scope.extras);
+    let mut neutral = vec![];
+    if zeros != 0 {
+        let zeros = Lit::Int(zeros, IntTy::Unsuffixed);
+        neutral.push(quote! {
+            fn neutral_zeros() -> #bits_ty {
+                #zeros
+            }
+        });
+    }
+    if ones != 0 {
+        let ones = Lit::Int(ones, IntTy::Unsuffixed);
+        neutral.push(quote! {
+            fn neutral_ones() -> #bits_ty {
+                #ones
+            }
+        });
+    }
+
+    let comment = &format!("Specification of the register `{}`", r.name)[..];
+    items.push(quote! {
+        #[doc = #comment]
+        pub struct #spec;
+
+        impl #root::RegisterSpec for #spec {
+            type Ux = #bits_ty;
+
+            fn mask() -> #bits_ty {
+                #mask
+            }
+
+            fn reset_value() -> #bits_ty {
+                #reset_value
+            }
+
+            #(#neutral)*
+        }
+    });
+
+    if is_readable(access) {
+        if let Some(doc) = read_side_effects(r, scope.extras) {
+            let doc = &doc[..];
+            items.push(quote! {
+                #[doc = #doc]
+                impl #root::ReadableWithSideEffects for #spec {}
+            });
+        } else {
+            items.push(quote! {
+                impl #root::Readable for #spec {}
+            });
+        }
+    }
+
+    if is_writable(access) {
+        items.push(quote! {
+            impl #root::Writable for #spec {}
+        });
+
+        if access == Access::WriteOnce || access == Access::ReadWriteOnce {
+            items.push(quote! {
+                impl #root::WriteOnce for #spec {}
+            });
+        } else {
+            items.push(quote! {
+                impl #root::Rewritable for #spec {}
+            });
+        }
+    }
+
+    if r.fields.is_some() {
+        items.push(quote! {
+            pub type #name = #root::Reg<#spec>;
+        });
+    } else {
+        items.push(quote! {
+            pub type #name = #root::RawReg<#spec>;
+        });
+    }
+
+    Ok(items)
+}
+
+#[doc(hidden)]
+pub fn gen_register_r(r: &Register,
+                      d: &Defaults,
+                      fields: &[svd::Field],
+                      opts: &Options)
+                      -> Result<Vec<Tokens>> {
+    gen_register_r_(r, d, fields, opts, &Scope::peripheral(&Extras::default()))
+}
+
+fn gen_register_r_(r: &Register,
+                   d: &Defaults,
+                   fields: &[svd::Field],
+                   opts: &Options,
+                   scope: &Scope)
+                   -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+    let root = &scope.root;
+
+    let reg_ty = type_of(r);
+    let name = Ident::new(format!("{}R", reg_ty));
+    let bits_ty = try!(bits_ty(r, d));
+
+    if opts.generic {
+        let spec = Ident::new(format!("{}Spec", reg_ty));
+        items.push(quote! {
+            pub type #name = #root::R<#spec>;
+        });
+    } else {
+        items.push(quote! {
+            #[derive(Clone, Copy)]
+            #[repr(C)]
+            pub struct #name {
+                bits: #bits_ty,
+            }
+        });
+    }
+
+    let mut impl_items = vec![];
+
+    for field in fields {
+        // Skip fields named RESERVED because, well, they are reserved so they
+        // shouldn't be modified/exposed
+        if field.name.to_lowercase() == "reserved" {
+            continue;
+        }
+
+        if field.access.map(is_readable) == Some(false) {
+            continue;
+        }
+
+        let name = Ident::new(field.name.to_snake_case().sanitize());
+        let offset = field.bit_range.offset as u8;
+
+        let width = field.bit_range.width;
+
+        if let Some(description) = field.description.as_ref() {
+            let bits = if width == 1 {
+                format!("Bit {}", field.bit_range.offset)
+            } else {
+                format!("Bits {}:{}",
+                        field.bit_range.offset,
+                        field.bit_range.offset + width - 1)
+            };
+
+            let comment = &format!("{} - {}", bits, respace(description))[..];
+            impl_items.push(quote! {
+                #[doc = #comment]
+            });
+        }
+
+        let field_ty = try!(field_ty(r, field));
+        let value = if width == 1 {
+            quote! {
+                const OFFSET: u8 = #offset;
+
+                self.bits & (1 << OFFSET) != 0
+            }
+        } else {
+            let mask = Lit::Int(mask(width), IntTy::Unsuffixed);
+
+            quote! {
+                const MASK: #bits_ty = #mask;
+                const OFFSET: u8 = #offset;
+
+                ((self.bits >> OFFSET) & MASK) as #field_ty
+            }
+        };
+
+        let item = if let Some(evs) = enumerated_values(field, Usage::Read) {
+            let proxy = Ident::new(format!("{}{}R",
+                                           reg_ty,
+                                           field.name.to_pascal_case()));
+            items.extend(try!(gen_field_r(r, field, evs)));
+
+            quote! {
+                pub fn #name(&self) -> #proxy {
+                    #proxy { bits: { #value } }
+                }
+            }
+        } else {
+            quote! {
+                pub fn #name(&self) -> #field_ty {
+                    #value
+                }
+            }
+        };
+
+        impl_items.push(item);
+    }
+
+    items.push(quote! {
+        impl #name {
+            #(#impl_items)*
+        }
+    });
+
+    Ok(items)
+}
+
+/// Generates the proxy returned by the reader of a field that has enumerated
+/// values
+fn gen_field_r(r: &Register,
+               f: &svd::Field,
+               evs: &EnumeratedValues)
+               -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+
+    let width = f.bit_range.width;
+    let field_ty = try!(field_ty(r, f));
+    let bits_fn = bits_fn(width);
+    let prefix = format!("{}{}", type_of(r), f.name.to_pascal_case());
+    let name = Ident::new(format!("{}R", prefix));
+    let enum_ = Ident::new(&*prefix);
+    let variants = variants(evs);
+
+    let comment = &format!("Value read from the field `{}`", f.name)[..];
+    items.push(quote! {
+        #[doc = #comment]
+        #[derive(Clone, Copy)]
+        pub struct #name {
+            bits: #field_ty,
+        }
+    });
+
+    let mut arms = variants.iter()
+        .map(|v| {
+            let pc = &v.pc;
+            let value = value_lit(width, v.value);
+            quote! {
+                #value => #enum_::#pc
+            }
+        })
+        .collect::<Vec<_>>();
+    if enum_has_reserved(r, f, width, &variants) {
+        arms.push(quote! {
+            bits => #enum_::_Reserved(bits)
+        });
+    }
+
+    let mut impl_items = vec![];
+    impl_items.push(quote! {
+        /// Value of the field as raw bits
+        pub fn #bits_fn(&self) -> #field_ty {
+            self.bits
+        }
+
+        /// Value of the field as an enumerated variant
+        pub fn variant(&self) -> #enum_ {
+            match self.bits {
+                #(#arms),*
+            }
+        }
+    });
+
+    for v in &variants {
+        let pc = &v.pc;
+        let is_variant = Ident::new(format!("is_{}", v.sc));
+        let comment = &format!("Checks if the value of the field is `{}`",
+                               v.pc)[..];
+
+        impl_items.push(quote! {
+            #[doc = #comment]
+            pub fn #is_variant(&self) -> bool {
+                self.variant() == #enum_::#pc
+            }
+        });
+    }
+
+    items.push(quote! {
+        impl #name {
+            #(#impl_items)*
+        }
+    });
+
+    Ok(items)
+}
+
+#[doc(hidden)]
+pub fn gen_register_w(r: &Register,
+                      d: &Defaults,
+                      fields: &[svd::Field],
+                      opts: &Options)
+                      -> Result<Vec<Tokens>> {
+    gen_register_w_(r, d, fields, opts, &Scope::peripheral(&Extras::default()))
+}
+
+fn gen_register_w_(r: &Register,
+                   d: &Defaults,
+                   fields: &[svd::Field],
+                   opts: &Options,
+                   scope: &Scope)
+                   -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+    let root = &scope.root;
+    let extras = scope.extras;
+
+    let reg_ty = type_of(r);
+    let name = Ident::new(format!("{}W", reg_ty));
+    let bits_ty = try!(bits_ty(r, d));
+
+    let mut impl_items = vec![];
+
+    if opts.generic {
+        // NOTE `reset_value` is provided by the generic writer
+        let spec = Ident::new(format!("{}Spec", reg_ty));
+        items.push(quote! {
+            pub type #name = #root::W<#spec>;
+        });
+    } else {
+        items.push(quote! {
+            #[derive(Clone, Copy)]
+            #[repr(C)]
+            pub struct #name {
+                bits: #bits_ty,
+            }
+        });
+
+        if let Some(reset_value) = reset_value(r, d, extras)
+            .map(|x| Lit::Int(x, IntTy::Unsuffixed)) {
+            impl_items.push(quote! {
+                /// Reset value
+                pub fn reset_value() -> Self {
+                    #name { bits: #reset_value }
+                }
+            });
+        }
+    }
+
+    for field in fields {
+        // Skip fields named RESERVED. See `gen_register_r` for an explanation
+        if field.name.to_lowercase() == "reserved" {
+            continue;
+        }
+
+        if field.access.map(is_writable) == Some(false) {
+            continue;
+        }
+
+        let name = Ident::new(field.name.to_snake_case().sanitize());
+        let offset = field.bit_range.offset as u8;
+
+        let width = field.bit_range.width;
+
+        if let Some(description) = field.description.as_ref() {
+            let bits = if width == 1 {
+                format!("Bit {}", field.bit_range.offset)
+            } else {
+                format!("Bits {}:{}",
                         field.bit_range.offset,
                         field.bit_range.offset + width - 1)
             };
@@ -785,63 +5346,1202 @@ pub fn gen_register_w(r: &Register,
             });
         }
 
-        let item = if width == 1 {
-            quote! {
-                pub fn #name(&mut self, value: bool) -> &mut Self {
-                    const OFFSET: u8 = #offset;
+        // The fields that have enumerated values, or whose values are
+        // constrained, are written through a proxy
+        let evs = enumerated_values(field, Usage::Write);
+        let constraint = write_constraint(r, field, extras);
+        if evs.is_some() || constraint.is_some() {
+            let proxy = Ident::new(format!("{}{}W",
+                                           reg_ty,
+                                           field.name.to_pascal_case()));
+            items.extend(try!(gen_field_w(r, d, field, evs, constraint)));
+
+            impl_items.push(quote! {
+                pub fn #name<'a>(&'a mut self) -> #proxy<'a> {
+                    #proxy { w: self }
+                }
+            });
+
+            continue;
+        }
+
+        let item = if width == 1 {
+            quote! {
+                pub fn #name(&mut self, value: bool) -> &mut Self {
+                    const OFFSET: u8 = #offset;
+
+                    if value {
+                        self.bits |= 1 << OFFSET;
+                    } else {
+                        self.bits &= !(1 << OFFSET);
+                    }
+                    self
+                }
+            }
+        } else {
+            let width_ty = try!(field_ty(r, field));
+            let mask = Lit::Int(mask(width), IntTy::Unsuffixed);
+
+            quote! {
+                pub fn #name(&mut self, value: #width_ty) -> &mut Self {
+                    const OFFSET: u8 = #offset;
+                    const MASK: #width_ty = #mask;
+
+                    self.bits &= !((MASK as #bits_ty) << OFFSET);
+                    self.bits |= ((value & MASK) as #bits_ty) << OFFSET;
+                    self
+                }
+            }
+        };
+
+        impl_items.push(item);
+    }
+
+    impl_items.extend(gen_field_intents(r, fields, extras));
+
+    items.push(quote! {
+        impl #name {
+            #(#impl_items)*
+        }
+    });
+
+    Ok(items)
+}
+
+/// Generates the methods of the writer of the register `r` that clear, set or
+/// toggle the fields whose `modifiedWriteValues` say so, e.g. `clear_uif` for
+/// a `oneToClear` field named `UIF`
+fn gen_field_intents(r: &Register,
+                     fields: &[svd::Field],
+                     extras: &Extras)
+                     -> Vec<Tokens> {
+    let mut items = vec![];
+    let access = access(r);
+
+    for field in fields {
+        if field.name.to_lowercase() == "reserved" ||
+           !is_writable(field.access.unwrap_or(access)) {
+            continue;
+        }
+
+        let mwv = extras.semantic(&r.name,
+                                  Some(&field.name),
+                                  |s| s.modified_write_values);
+        let (verb, ones) = match mwv {
+            Some(ModifiedWriteValues::OneToClear) => ("clear", true),
+            Some(ModifiedWriteValues::OneToSet) => ("set", true),
+            Some(ModifiedWriteValues::OneToToggle) => ("toggle", true),
+            Some(ModifiedWriteValues::ZeroToClear) => ("clear", false),
+            Some(ModifiedWriteValues::ZeroToSet) => ("set", false),
+            Some(ModifiedWriteValues::ZeroToToggle) => ("toggle", false),
+            _ => continue,
+        };
+
+        let name = format!("{}_{}", verb, field.name.to_snake_case())
+            .sanitize();
+        // don't shadow the writer of another field
+        if fields.iter().any(|f| f.name.to_snake_case().sanitize() == name) {
+            continue;
+        }
+        let name = Ident::new(name);
+
+        let mask = Lit::Int(mask(field.bit_range.width) <<
+                            field.bit_range.offset,
+                            IntTy::Unsuffixed);
+        let (value, write) = if ones {
+            ("ones", quote! { self.bits |= #mask; })
+        } else {
+            ("zeros", quote! { self.bits &= !#mask; })
+        };
+        let comment = &format!("{}s the field `{}` by writing {} to it",
+                               verb.to_pascal_case(),
+                               field.name,
+                               value)[..];
+
+        items.push(quote! {
+            #[doc = #comment]
+            pub fn #name(&mut self) -> &mut Self {
+                #write
+                self
+            }
+        });
+    }
+
+    items
+}
+
+/// Returns the documentation of the readers of the register `r` if its reads
+/// have side effects, as described by the `readAction` of the register or of
+/// its fields
+fn read_side_effects(r: &Register, extras: &Extras) -> Option<String> {
+    let mut effects = vec![];
+    if let Some(action) = extras.semantic(&r.name, None, |s| s.read_action) {
+        effects.push(action.effect("the register"));
+    }
+    for f in r.fields.iter().flat_map(|fs| fs.iter()) {
+        if let Some(action) = extras.semantics
+            .get(&format!("{}.{}", r.name, f.name))
+            .and_then(|s| s.read_action) {
+            effects.push(action.effect(&format!("the field `{}`", f.name)));
+        }
+    }
+
+    if effects.is_empty() {
+        None
+    } else {
+        Some(format!("Reads the register\n\n**NOTE** Reading this register \
+                      has side effects: it {}.",
+                     effects.join(", ")))
+    }
+}
+
+/// Returns the bits of the register `r` that must be written as zeros, and
+/// the ones that must be written as ones, to leave the fields whose writes
+/// have side effects, e.g. `oneToClear` flags, unchanged
+fn neutral_bits(r: &Register, extras: &Extras) -> (u64, u64) {
+    let (mut zeros, mut ones) = (0, 0);
+
+    for f in r.fields.iter().flat_map(|fs| fs.iter()) {
+        let mask = mask(f.bit_range.width) << f.bit_range.offset;
+        match extras.semantic(&r.name,
+                      Some(&f.name),
+                      |s| s.modified_write_values)
+            .and_then(|mwv| mwv.neutral()) {
+            Some(false) => zeros |= mask,
+            Some(true) => ones |= mask,
+            None => {}
+        }
+    }
+
+    (zeros, ones)
+}
+
+/// Returns the `writeConstraint` of the field `f` of the register `r`, unless
+/// it allows all the values of the field
+///
+/// `useEnumeratedValues` doesn't constrain the fields that have no enumerated
+/// values, e.g. the ones next to the fields that do, in a register that has
+/// that constraint.
+fn write_constraint(r: &Register,
+                    f: &svd::Field,
+                    extras: &Extras)
+                    -> Option<WriteConstraint> {
+    let field = extras.semantics
+        .get(&format!("{}.{}", r.name, f.name))
+        .and_then(|s| s.write_constraint);
+    // the range of a register doesn't apply to its fields
+    let register = extras.semantics
+        .get(&r.name)
+        .and_then(|s| s.write_constraint)
+        .and_then(|wc| match wc {
+            WriteConstraint::Range(..) => None,
+            wc => Some(wc),
+        });
+
+    match field.or(register) {
+        Some(WriteConstraint::Range(0, max))
+            if max >= mask(f.bit_range.width) => None,
+        Some(WriteConstraint::UseEnumeratedValues)
+            if enumerated_values(f, Usage::Write).is_none() => None,
+        wc => wc,
+    }
+}
+
+/// Generates the proxy returned by the writer of a field that has enumerated
+/// values, or whose values are constrained
+fn gen_field_w(r: &Register,
+               d: &Defaults,
+               f: &svd::Field,
+               evs: Option<&EnumeratedValues>,
+               constraint: Option<WriteConstraint>)
+               -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+
+    let width = f.bit_range.width;
+    let field_ty = try!(field_ty(r, f));
+    let bits_fn = bits_fn(width);
+    let bits_ty = try!(bits_ty(r, d));
+    let reg_w = Ident::new(format!("{}W", type_of(r)));
+    let prefix = format!("{}{}", type_of(r), f.name.to_pascal_case());
+    let name = Ident::new(format!("{}W", prefix));
+    let enum_ = Ident::new(&*prefix);
+    let variants = evs.map(variants).unwrap_or_else(Vec::new);
+    let offset = f.bit_range.offset as u8;
+
+    let comment = &format!("Proxy to write the field `{}`", f.name)[..];
+    items.push(quote! {
+        #[doc = #comment]
+        pub struct #name<'a> {
+            w: &'a mut #reg_w,
+        }
+    });
+
+    let mut impl_items = vec![];
+
+    // Writing raw bits is `unsafe` when the `writeConstraint` of the field
+    // says that some of its values are not valid
+    let safety = match constraint {
+        None => None,
+        Some(WriteConstraint::UseEnumeratedValues) => {
+            if is_exhaustive(width, &variants) {
+                None
+            } else {
+                Some(format!("Only the values of the variants of `{}` are \
+                              valid values of this field",
+                             enum_))
+            }
+        }
+        Some(WriteConstraint::Range(min, max)) => {
+            Some(format!("Only the values between `{}` and `{}`, inclusive, \
+                          are valid values of this field",
+                         min,
+                         max))
+        }
+        Some(WriteConstraint::WriteAsRead) => {
+            Some("Only the value that was last read from this field is a \
+                  valid value of it"
+                .to_owned())
+        }
+    };
+    let (unsafety, write_doc) = match safety {
+        Some(safety) => {
+            (Some(Ident::new("unsafe")),
+             format!("Writes raw bits to the field\n\n# Safety\n\n{}",
+                     safety))
+        }
+        None => (None, "Writes raw bits to the field".to_owned()),
+    };
+    let write_doc = &write_doc[..];
+    let write = |value: Tokens| if unsafety.is_some() {
+        quote! { unsafe { self.#bits_fn(#value) } }
+    } else {
+        quote! { self.#bits_fn(#value) }
+    };
+
+    // None of the variants is the value that was last read
+    let variant_unsafety = if constraint == Some(WriteConstraint::WriteAsRead) {
+        Some(Ident::new("unsafe"))
+    } else {
+        None
+    };
 
-                    if value {
-                        self.bits |= 1 << OFFSET;
-                    } else {
-                        self.bits &= !(1 << OFFSET);
-                    }
-                    self
-                }
+    // The enumeration has a `_Reserved` variant when the field can be read
+    // and not all its values have a name; that variant must not be written.
+    // If all the values have a name the variant only holds bits that don't
+    // fit in the field, which the writer masks out.
+    if evs.is_some() &&
+       (!enum_has_reserved(r, f, width, &variants) ||
+        is_exhaustive(width, &variants)) {
+        let write = write(quote! { variant.#bits_fn() });
+        impl_items.push(quote! {
+            /// Writes `variant` to the field
+            pub #variant_unsafety fn variant(self, variant: #enum_)
+                                             -> &'a mut #reg_w {
+                #write
             }
-        } else {
-            let width_ty = width.to_ty();
-            let mask = (1 << width) - 1;
-            let mask = Lit::Int(mask, IntTy::Unsuffixed);
+        });
+    }
 
-            quote! {
-                pub fn #name(&mut self, value: #width_ty) -> &mut Self {
-                    const OFFSET: u8 = #offset;
-                    const MASK: #width_ty = #mask;
+    for v in &variants {
+        let sc = &v.sc;
+        let write = write(value_lit(width, v.value));
+        let comment = &v.doc[..];
 
-                    self.bits &= !((MASK as #bits_ty) << OFFSET);
-                    self.bits |= ((value & MASK) as #bits_ty) << OFFSET;
-                    self
+        impl_items.push(quote! {
+            #[doc = #comment]
+            pub #variant_unsafety fn #sc(self) -> &'a mut #reg_w {
+                #write
+            }
+        });
+    }
+
+    if width == 1 {
+        impl_items.push(quote! {
+            #[doc = #write_doc]
+            pub #unsafety fn bit(self, value: bool) -> &'a mut #reg_w {
+                const OFFSET: u8 = #offset;
+
+                if value {
+                    self.w.bits |= 1 << OFFSET;
+                } else {
+                    self.w.bits &= !(1 << OFFSET);
                 }
+                self.w
+            }
+        });
+    } else {
+        let mask = Lit::Int(mask(width), IntTy::Unsuffixed);
+
+        impl_items.push(quote! {
+            #[doc = #write_doc]
+            pub #unsafety fn bits(self, value: #field_ty) -> &'a mut #reg_w {
+                const OFFSET: u8 = #offset;
+                const MASK: #field_ty = #mask;
+
+                self.w.bits &= !((MASK as #bits_ty) << OFFSET);
+                self.w.bits |= ((value & MASK) as #bits_ty) << OFFSET;
+                self.w
             }
+        });
+    }
+
+    if let Some(WriteConstraint::Range(min, max)) = constraint {
+        let checked_fn = Ident::new(format!("checked_{}", bits_fn));
+        let value = if width == 1 {
+            quote! { (value as u8) }
+        } else {
+            quote! { value }
+        };
+        // NOTE comparing an unsigned value against zero triggers a warning
+        let mut checks = vec![];
+        if min != 0 {
+            let min = Lit::Int(min, IntTy::Unsuffixed);
+            checks.push(quote! { #value < #min });
+        }
+        let max = Lit::Int(max, IntTy::Unsuffixed);
+        checks.push(quote! { #value > #max });
+        let field_ty = if width == 1 {
+            quote! { bool }
+        } else {
+            quote! { #field_ty }
         };
 
-        impl_items.push(item);
+        impl_items.push(quote! {
+            /// Writes raw bits to the field if they are a valid value of it
+            pub fn #checked_fn(self, value: #field_ty)
+                               -> Option<&'a mut #reg_w> {
+                if #(#checks)||* {
+                    None
+                } else {
+                    Some(unsafe { self.#bits_fn(value) })
+                }
+            }
+        });
     }
 
     items.push(quote! {
-        impl #name {
+        impl<'a> #name<'a> {
             #(#impl_items)*
         }
     });
 
-    items
+    Ok(items)
+}
+
+/// Generates the enumerations that list the possible values of the fields of
+/// a register
+#[doc(hidden)]
+pub fn gen_register_values(r: &Register,
+                           fields: &[svd::Field])
+                           -> Result<Vec<Tokens>> {
+    let mut items = vec![];
+    let access = access(r);
+
+    for field in fields {
+        if field.name.to_lowercase() == "reserved" {
+            continue;
+        }
+
+        let readable = is_readable(access) &&
+                       field.access.map(is_readable) != Some(false);
+        let writable = is_writable(access) &&
+                       field.access.map(is_writable) != Some(false);
+
+        let evs = match (readable, writable) {
+            (true, true) => {
+                enumerated_values(field, Usage::Read)
+                    .or_else(|| enumerated_values(field, Usage::Write))
+            }
+            (true, false) => enumerated_values(field, Usage::Read),
+            (false, true) => enumerated_values(field, Usage::Write),
+            (false, false) => None,
+        };
+
+        let evs = if let Some(evs) = evs {
+            evs
+        } else {
+            continue;
+        };
+
+        let width = field.bit_range.width;
+        let field_ty = try!(field_ty(r, field));
+        let bits_fn = bits_fn(width);
+        let name = Ident::new(format!("{}{}",
+                                      type_of(r),
+                                      field.name.to_pascal_case()));
+        let variants = variants(evs);
+
+        let mut vars = vec![];
+        let mut arms = vec![];
+        for v in &variants {
+            let pc = &v.pc;
+            let value = value_lit(width, v.value);
+            let comment = &v.doc[..];
+
+            vars.push(quote! {
+                #[doc = #comment]
+                #pc
+            });
+            arms.push(quote! {
+                #name::#pc => #value
+            });
+        }
+
+        if enum_has_reserved(r, field, width, &variants) {
+            vars.push(quote! {
+                /// Value that has no name in the SVD file
+                _Reserved(#field_ty)
+            });
+            arms.push(quote! {
+                #name::_Reserved(bits) => bits
+            });
+        }
+
+        let comment = &format!("Possible values of the field `{}`",
+                               field.name)[..];
+        items.push(quote! {
+            #[doc = #comment]
+            #[derive(Clone, Copy, Debug, PartialEq)]
+            pub enum #name {
+                #(#vars),*
+            }
+
+            impl #name {
+                /// Value of the variant as raw bits
+                pub fn #bits_fn(&self) -> #field_ty {
+                    match *self {
+                        #(#arms),*
+                    }
+                }
+            }
+        });
+    }
+
+    Ok(items)
+}
+
+/// A named value of a field
+struct Variant {
+    doc: String,
+    pc: Ident,
+    sc: Ident,
+    value: u32,
+}
+
+/// Returns the enumerated values of `f`, if they are meant to be used when
+/// the field is accessed in the `usage` way
+fn enumerated_values(f: &svd::Field,
+                     usage: Usage)
+                     -> Option<&EnumeratedValues> {
+    f.enumerated_values.as_ref().and_then(|evs| {
+        let matches = match (evs.usage, usage) {
+            (None, _) |
+            (Some(Usage::ReadWrite), _) |
+            (Some(Usage::Read), Usage::Read) |
+            (Some(Usage::Write), Usage::Write) => true,
+            _ => false,
+        };
+
+        if matches && evs.values.iter().any(|ev| ev.value.is_some()) {
+            Some(evs)
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the named values of `evs`. Values that are only marked as
+/// `isDefault` are skipped.
+fn variants(evs: &EnumeratedValues) -> Vec<Variant> {
+    evs.values
+        .iter()
+        .filter_map(|ev| {
+            ev.value.map(|value| {
+                Variant {
+                    doc: ev.description
+                        .as_ref()
+                        .map(|d| respace(d))
+                        .unwrap_or_else(|| ev.name.clone()),
+                    pc: Ident::new(digit_prefix(ev.name.to_pascal_case())),
+                    sc: Ident::new(digit_prefix(ev.name
+                        .to_snake_case()
+                        .sanitize())),
+                    value: value,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Do `variants` cover all the possible values of a field of `width` bits?
+fn is_exhaustive(width: u32, variants: &[Variant]) -> bool {
+    if width >= 32 {
+        return false;
+    }
+
+    let mut values = variants.iter()
+        .map(|v| v.value)
+        .filter(|&v| (v as u64) < 1 << width)
+        .collect::<Vec<_>>();
+    values.sort();
+    values.dedup();
+    values.len() as u64 == 1 << width
+}
+
+/// Does the enumeration of the values of `f` need a `_Reserved` variant?
+///
+/// The reader maps the raw values that have no name to it. Unless the field
+/// is a `bool` or fills its integer type, these include the values that don't
+/// fit in the field, even though the reader masks them out.
+fn enum_has_reserved(r: &Register,
+                     f: &svd::Field,
+                     width: u32,
+                     variants: &[Variant])
+                     -> bool {
+    let fills_ty = width == 1 || width == 8 || width == 16;
+    is_readable(access(r)) && f.access.map(is_readable) != Some(false) &&
+    enumerated_values(f, Usage::Read).is_some() &&
+    !(fills_ty && is_exhaustive(width, variants))
+}
+
+/// Mask that covers `width` bits, e.g. `0b111` for a `width` of 3
+fn mask(width: u32) -> u64 {
+    if width >= 64 {
+        !0
+    } else {
+        (1 << width) - 1
+    }
+}
+
+/// Reset value of the register `r`
+///
+/// `svd-parser` reads it as a 32-bit value so the one that `extras` has, if
+/// any, takes precedence.
+fn reset_value(r: &RegisterInfo, d: &Defaults, extras: &Extras) -> Option<u64> {
+    match extras.resets.get(&r.name) {
+        Some(reset) => reset.value,
+        None => r.reset_value.or(d.reset_value).map(|x| x as u64),
+    }
+}
+
+/// Reset mask of the register `r`
+///
+/// Like the reset value, the one that `extras` has, if any, takes precedence.
+fn reset_mask(r: &RegisterInfo, d: &Defaults, extras: &Extras) -> Option<u64> {
+    match extras.resets.get(&r.name) {
+        Some(reset) => reset.mask,
+        None => r.reset_mask.or(d.reset_mask).map(|x| x as u64),
+    }
+}
+
+/// Size, in bits, of the register `r`
+fn size_of(r: &RegisterInfo, d: &Defaults) -> Result<u32> {
+    r.size.or(d.size).ok_or_else(|| Error::NoSize { path: r.name.clone() })
+}
+
+/// Size, in bytes, of the integer used to store the value of the register `r`
+fn storage_size(r: &RegisterInfo, d: &Defaults) -> Result<u32> {
+    Ok(cmp::max(try!(size_of(r, d)).next_power_of_two(), 8) / 8)
+}
+
+/// Type used to store the value of the register `r`
+fn bits_ty(r: &RegisterInfo, d: &Defaults) -> Result<Ident> {
+    try!(size_of(r, d)).to_ty().map_err(|e| e.within(&r.name))
+}
+
+/// Type used to represent the value of the field `f` of the register `r`
+fn field_ty(r: &RegisterInfo, f: &svd::Field) -> Result<Ident> {
+    if f.bit_range.width == 1 {
+        Ok(Ident::new("bool"))
+    } else {
+        f.bit_range
+            .width
+            .to_ty()
+            .map_err(|e| e.within(&f.name).within(&r.name))
+    }
+}
+
+/// Name of the method that accesses the raw value of a field of `width` bits
+fn bits_fn(width: u32) -> Ident {
+    if width == 1 {
+        Ident::new("bit")
+    } else {
+        Ident::new("bits")
+    }
+}
+
+/// Literal of the value `value` of a field of `width` bits
+fn value_lit(width: u32, value: u32) -> Tokens {
+    if width == 1 {
+        if value == 0 {
+            quote! { false }
+        } else {
+            quote! { true }
+        }
+    } else {
+        let value = Lit::Int(value as u64, IntTy::Unsuffixed);
+        quote! { #value }
+    }
+}
+
+/// Prefixes `name` with an underscore if it starts with a digit
+fn digit_prefix(name: String) -> String {
+    if name.starts_with(|c: char| c.is_digit(10)) {
+        format!("_{}", name)
+    } else {
+        name
+    }
 }
 
 trait U32Ext {
-    fn to_ty(&self) -> Ident;
+    fn to_ty(&self) -> Result<Ident>;
 }
 
 impl U32Ext for u32 {
-    fn to_ty(&self) -> Ident {
-        match *self {
-            1...8 => Ident::new("u8"),
-            9...16 => Ident::new("u16"),
-            17...32 => Ident::new("u32"),
-            _ => panic!("{}.to_ty()", *self),
+    fn to_ty(&self) -> Result<Ident> {
+        let width = *self;
+        if width == 0 || width > 64 {
+            return Err(Error::TooWide {
+                path: String::new(),
+                width: width,
+            });
+        }
+
+        Ok(if width <= 8 {
+            Ident::new("u8")
+        } else if width <= 16 {
+            Ident::new("u16")
+        } else if width <= 32 {
+            Ident::new("u32")
+        } else {
+            Ident::new("u64")
+        })
+    }
+}
+
+/// Returns the tokens of `path`, e.g. `::volatile_register`, as an absolute
+/// path
+///
+/// A relative path, e.g. `vendored::volatile`, names a module of the crate
+/// root. The code that uses it lives in nested modules so it gets rooted.
+fn path_tokens(path: &str) -> Tokens {
+    let mut t = Tokens::new();
+    t.append("::");
+    let path = if path.starts_with("::") {
+        &path[2..]
+    } else {
+        path
+    };
+
+    for (i, segment) in path.split("::").enumerate() {
+        if i != 0 {
+            t.append("::");
         }
+        t.append(segment.trim());
     }
+    t
+}
+
+/// Formats `n` as an hexadecimal literal, e.g. `0x100000000`
+fn hex64(n: u64) -> Tokens {
+    let mut t = Tokens::new();
+    t.append(&format!("0x{:x}", n));
+    t
+}
+
+/// Formats `n` as an hexadecimal literal, e.g. `0x40005400`
+fn hex(n: u32) -> Tokens {
+    let mut t = Tokens::new();
+    t.append(&format!("0x{:08x}", n));
+    t
 }
 
 fn respace(s: &str) -> String {
     s.split_whitespace().collect::<Vec<_>>().join(" ")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Named values of a field, one per value in `values`
+    fn variants_of(values: &[u32]) -> Vec<Variant> {
+        values.iter()
+            .map(|&value| {
+                let name = format!("V{}", value);
+                Variant {
+                    doc: name.clone(),
+                    pc: Ident::new(&*name),
+                    sc: Ident::new(name.to_lowercase()),
+                    value: value,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exhaustive_variants() {
+        assert!(is_exhaustive(1, &variants_of(&[1, 0])));
+        assert!(is_exhaustive(2, &variants_of(&[0, 1, 2, 3])));
+        // duplicated values count once
+        assert!(!is_exhaustive(2, &variants_of(&[0, 1, 2, 2])));
+        // values that don't fit in the field don't count
+        assert!(!is_exhaustive(2, &variants_of(&[0, 1, 2, 4])));
+        assert!(!is_exhaustive(3, &variants_of(&[0, 1, 2, 3])));
+        assert!(!is_exhaustive(32, &variants_of(&[0, 1])));
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(parse_u32("0x400"), Some(0x400));
+        assert_eq!(parse_u32("0XfF"), Some(0xff));
+        assert_eq!(parse_u32("8"), Some(8));
+        assert_eq!(parse_u32("0x"), None);
+        assert_eq!(parse_u32("-1"), None);
+        assert_eq!(parse_u32("eight"), None);
+    }
+
+    #[test]
+    fn dim_indices() {
+        assert_eq!(parse_dim_index("0-3"),
+                   Some(vec!["0".to_owned(),
+                             "1".to_owned(),
+                             "2".to_owned(),
+                             "3".to_owned()]));
+        assert_eq!(parse_dim_index("A, B,C"),
+                   Some(vec!["A".to_owned(), "B".to_owned(), "C".to_owned()]));
+        assert_eq!(parse_dim_index("3-1"), None);
+        assert_eq!(parse_dim_index("A-C"), None);
+        assert_eq!(parse_dim_index("3"), None);
+    }
+
+    /// Parses a cluster whose registers are given as `(offset, size)` pairs
+    fn cluster_of(array: &str, registers: &[(u32, u32)]) -> Cluster {
+        let registers = registers.iter()
+            .enumerate()
+            .map(|(i, &(offset, size))| {
+                format!("<register><name>R{0}</name>\
+                         <description>R{0}</description>\
+                         <addressOffset>{1}</addressOffset>\
+                         <size>{2}</size></register>",
+                        i,
+                        offset,
+                        size)
+            })
+            .collect::<String>();
+        let xml = format!("<cluster><name>C%s</name>\
+                           <addressOffset>0</addressOffset>{}{}</cluster>",
+                          array,
+                          registers);
+        let xml = xmltree::Element::parse(xml.as_bytes()).unwrap();
+        cluster(&xml, Reset::default()).unwrap()
+    }
+
+    fn defaults() -> Defaults {
+        Defaults {
+            size: None,
+            reset_value: None,
+            reset_mask: None,
+            access: None,
+        }
+    }
+
+    #[test]
+    fn cluster_padding() {
+        // 5 bytes of registers, padded up to the alignment of the `u32`
+        let c = cluster_of("", &[(0, 32), (4, 8)]);
+        assert_eq!(cluster_layout(&c, &defaults()).unwrap(), (8, 4));
+
+        // the elements of an array are as large as its stride
+        let c = cluster_of("<dim>2</dim><dimIncrement>0x10</dimIncrement>",
+                           &[(0, 16), (2, 8)]);
+        assert_eq!(cluster_layout(&c, &defaults()).unwrap(), (16, 2));
+    }
+
+    #[test]
+    fn cluster_overlap() {
+        let c = cluster_of("<dim>2</dim><dimIncrement>4</dimIncrement>",
+                           &[(0, 32), (4, 32)]);
+        assert!(cluster_layout(&c, &defaults()).is_err());
+    }
+
+    #[test]
+    fn cluster_misaligned() {
+        let c = cluster_of("<dim>2</dim><dimIncrement>6</dimIncrement>",
+                           &[(0, 32)]);
+        assert!(cluster_layout(&c, &defaults()).is_err());
+    }
+
+    /// SVD file of a device whose only peripheral, `P`, has the `registers`
+    fn device(registers: &str) -> String {
+        format!("<device><name>D</name><size>32</size><peripherals>\
+                 <peripheral><name>P</name><baseAddress>0</baseAddress>\
+                 <registers>{}</registers></peripheral></peripherals>\
+                 </device>",
+                registers)
+    }
+
+    /// A register at offset 0 that has the `fields`, plus the `extra` elements
+    fn register(name: &str, fields: &[String], extra: &str) -> String {
+        format!("<register><name>{}</name><description>{0}</description>\
+                 <addressOffset>0</addressOffset>{}<fields>{}</fields>\
+                 </register>",
+                name,
+                extra,
+                fields.concat())
+    }
+
+    /// A field of `width` bits at `offset`, plus the `extra` elements
+    fn field(name: &str, offset: u32, width: u32, extra: &str) -> String {
+        format!("<field><name>{}</name><description>{0}</description>\
+                 <bitOffset>{}</bitOffset><bitWidth>{}</bitWidth>{}</field>",
+                name,
+                offset,
+                width,
+                extra)
+    }
+
+    /// Parses the SVD file `xml`, whose only peripheral is `P`, and returns its
+    /// first register plus the extras of `P`
+    fn first_register(xml: &str) -> (Device, Register, Extras) {
+        let (d, opts) = parse(xml).unwrap();
+        let r = d.peripherals[0].registers.as_ref().unwrap()[0].clone();
+        let extras = opts.peripheral_extras
+            .get("P")
+            .cloned()
+            .unwrap_or_default();
+        (d, r, extras)
+    }
+
+    #[test]
+    fn reset_value_64() {
+        let xml = device(&register("R",
+                                   &[field("F", 0, 64, "")],
+                                   "<size>64</size>\
+                                    <resetValue>0x100000000</resetValue>"));
+        let (d, r, extras) = first_register(&xml);
+        assert_eq!(reset_value(&r, &d.defaults, &extras), Some(0x100000000));
+
+        let opts = parse(&xml).unwrap().1;
+        let code = gen_device_with(&d, &opts).unwrap();
+        assert!(code.as_str().contains("RW { bits : 4294967296 }"));
+    }
+
+    #[test]
+    fn neutral_bits_of_modified_write_values() {
+        let mwv = |v: &str| {
+            format!("<modifiedWriteValues>{}</modifiedWriteValues>", v)
+        };
+        let fields = [field("A", 0, 1, &mwv("oneToClear")),
+                      field("B", 1, 1, &mwv("zeroToClear")),
+                      field("C", 2, 1, &mwv("modify"))];
+        let (_, r, extras) =
+            first_register(&device(&register("SR", &fields, "")));
+        assert_eq!(neutral_bits(&r, &extras), (0b01, 0b10));
+    }
+
+    #[test]
+    fn write_constraints() {
+        let range = |min: u32, max: u32| {
+            format!("<writeConstraint><range><minimum>{}</minimum>\
+                     <maximum>{}</maximum></range></writeConstraint>",
+                    min,
+                    max)
+        };
+        let enums = "<enumeratedValues><enumeratedValue><name>Off</name>\
+                     <value>0</value></enumeratedValue></enumeratedValues>";
+        let use_enums = "<writeConstraint><useEnumeratedValues>true\
+                         </useEnumeratedValues></writeConstraint>";
+        let fields = [field("PSC", 0, 4, &range(1, 9)),
+                      field("FULL", 4, 2, &range(0, 3)),
+                      field("MODE", 6, 2, enums),
+                      field("DATA", 8, 8, "")];
+        let (_, r, extras) =
+            first_register(&device(&register("CR", &fields, use_enums)));
+        let fields = r.fields.as_ref().unwrap();
+
+        assert_eq!(write_constraint(&r, &fields[0], &extras),
+                   Some(WriteConstraint::Range(1, 9)));
+        // a range that allows all the values is no constraint
+        assert_eq!(write_constraint(&r, &fields[1], &extras), None);
+        // the `useEnumeratedValues` of the register applies to the fields
+        // that have enumerated values, and only to them
+        assert_eq!(write_constraint(&r, &fields[2], &extras),
+                   Some(WriteConstraint::UseEnumeratedValues));
+        assert_eq!(write_constraint(&r, &fields[3], &extras), None);
+    }
+
+    #[test]
+    fn checked_bits() {
+        let range = |min: u32, max: u32| {
+            format!("<writeConstraint><range><minimum>{}</minimum>\
+                     <maximum>{}</maximum></range></writeConstraint>",
+                    min,
+                    max)
+        };
+        let fields = [field("PSC", 0, 4, &range(1, 9)),
+                      field("LOW", 4, 2, &range(0, 2))];
+        let xml = device(&register("CR", &fields, ""));
+        let (d, opts) = parse(&xml).unwrap();
+        let code = gen_device_with(&d, &opts).unwrap();
+        let code = code.as_str();
+
+        assert!(code.contains("pub unsafe fn bits ( self , value : u8 )"));
+        assert!(code.contains("if value < 1 || value > 9 { None }"));
+        // NOTE no lower bound check for a range that starts at zero
+        assert!(code.contains("if value > 2 { None }"));
+    }
+
+    /// SVD file of a device whose peripherals have the `interrupts`, by
+    /// peripheral
+    fn interrupt_device(interrupts: &[&[(&str, u32)]]) -> String {
+        let mut peripherals = String::new();
+        for (n, is) in interrupts.iter().enumerate() {
+            peripherals.push_str(&format!("<peripheral><name>P{}</name>\
+                                           <baseAddress>0</baseAddress>",
+                                          n));
+            for &(name, value) in is.iter() {
+                peripherals.push_str(&format!("<interrupt><name>{}</name>\
+                                               <value>{}</value></interrupt>",
+                                              name,
+                                              value));
+            }
+            peripherals.push_str("</peripheral>");
+        }
+
+        format!("<device><name>D</name><peripherals>{}</peripherals>\
+                 </device>",
+                peripherals)
+    }
+
+    #[test]
+    fn interrupts_of_peripherals() {
+        let xml = interrupt_device(&[&[("B", 3), ("A", 1)],
+                                     &[("C", 2), ("A", 1)]]);
+        let (d, opts) = parse(&xml).unwrap();
+        let names = interrupts(&d, &opts)
+            .unwrap()
+            .into_iter()
+            .map(|i| (i.name, i.value))
+            .collect::<Vec<_>>();
+        assert_eq!(names,
+                   [("A".to_owned(), 1), ("C".to_owned(), 2),
+                    ("B".to_owned(), 3)]);
+        assert_eq!(gen_device_x_with(&d, &opts).unwrap(),
+                   "PROVIDE(A = DefaultHandler);\n\
+                    PROVIDE(C = DefaultHandler);\n\
+                    PROVIDE(B = DefaultHandler);\n");
+
+        for xml in &[interrupt_device(&[&[("A", 1)], &[("B", 1)]]),
+                     interrupt_device(&[&[("A", 1), ("A", 2)]]),
+                     interrupt_device(&[&[("A", 256)]])] {
+            let (d, opts) = parse(xml).unwrap();
+            assert!(gen_device_with(&d, &opts).is_err());
+        }
+    }
+
+    #[test]
+    fn shared_register_blocks() {
+        let a = register("A", &[field("F", 0, 1, "")], "");
+        let b = register("B", &[], "").replace("<addressOffset>0<",
+                                               "<addressOffset>4<");
+        let c = register("A", &[field("F", 0, 2, "")], "");
+        let peripheral = |name: &str, registers: &str| {
+            format!("<peripheral><name>{}</name><baseAddress>0</baseAddress>\
+                     <registers>{}</registers></peripheral>",
+                    name,
+                    registers)
+        };
+        let xml = format!("<device><name>D</name><peripherals>{}{}{}\
+                           <peripheral derivedFrom=\"P\"><name>S</name>\
+                           <baseAddress>8</baseAddress></peripheral>\
+                           </peripherals></device>",
+                          peripheral("P", &format!("{}{}", a, b)),
+                          peripheral("Q", &format!("{}{}", b, a)),
+                          peripheral("R", &format!("{}{}", c, b)));
+        let (d, opts) = parse(&xml).unwrap();
+        let ps = peripherals(&d, &[]).unwrap();
+        let extras = resolve_extras(&d, &opts).unwrap();
+        let none = Extras::default();
+        let same = |i: usize, j: usize| {
+            same_block(&ps[i],
+                       extras.get(&ps[i].name).unwrap_or(&none),
+                       &ps[j],
+                       extras.get(&ps[j].name).unwrap_or(&none))
+        };
+        assert!(same(0, 1));
+        assert!(!same(0, 2));
+        assert!(same(0, 3));
+    }
+
+    /// SVD file of a device with the `peripherals`, given as their name,
+    /// what they are derived from, and their registers
+    fn derived_device(peripherals: &[(&str, Option<&str>, &str)]) -> String {
+        let mut xml = String::new();
+        for &(name, base, registers) in peripherals {
+            let derived_from = base.map(|b| format!(" derivedFrom=\"{}\"", b))
+                .unwrap_or_default();
+            let registers = if registers.is_empty() {
+                String::new()
+            } else {
+                format!("<registers>{}</registers>", registers)
+            };
+            xml.push_str(&format!("<peripheral{}><name>{}</name>\
+                                   <baseAddress>0</baseAddress>{}\
+                                   </peripheral>",
+                                  derived_from,
+                                  name,
+                                  registers));
+        }
+
+        format!("<device><name>D</name><size>32</size><peripherals>{}\
+                 </peripherals></device>",
+                xml)
+    }
+
+    #[test]
+    fn resolve_chains() {
+        let evs = "<enumeratedValues><name>E</name><enumeratedValue>\
+                   <name>ON</name><value>1</value></enumeratedValue>\
+                   </enumeratedValues>";
+        let a = register("A", &[field("F", 0, 1, evs)], "");
+        let b = register("B",
+                         &[field("G",
+                                 1,
+                                 1,
+                                 "<enumeratedValues derivedFrom=\"E\">\
+                                  </enumeratedValues>")],
+                         "<addressOffset>4</addressOffset>")
+            .replace("<addressOffset>0</addressOffset>", "");
+        let b2 = register("B", &[], "<addressOffset>8</addressOffset>")
+            .replace("<addressOffset>0</addressOffset>", "");
+        let xml = derived_device(&[("R", Some("Q"), ""),
+                                   ("Q", Some("P"), &b2),
+                                   ("P", None, &format!("{}{}", a, b))]);
+        let (d, _) = parse(&xml).unwrap();
+        let d = resolve(&d).unwrap();
+
+        let registers = d.peripherals[0].registers.as_ref().unwrap();
+        assert!(d.peripherals[0].derived_from.is_none());
+        assert_eq!(registers.iter()
+                       .map(|r| (&r.name[..], r.address_offset))
+                       .collect::<Vec<_>>(),
+                   [("A", 0), ("B", 8)]);
+
+        let g = &d.peripherals[2].registers.as_ref().unwrap()[1]
+            .fields
+            .as_ref()
+            .unwrap()[0];
+        let evs = g.enumerated_values.as_ref().unwrap();
+        assert!(evs.derived_from.is_none());
+        assert_eq!(evs.values[0].name, "ON");
+    }
+
+    #[test]
+    fn resolve_cycles() {
+        let a = register("A", &[field("F", 0, 1, "")], "");
+        let xml = derived_device(&[("P", Some("Q"), ""),
+                                   ("Q", Some("R"), ""),
+                                   ("R", Some("p"), &a)]);
+        let (d, opts) = parse(&xml).unwrap();
+        match resolve(&d) {
+            Err(Error::DerivedFromCycle { path, cycle }) => {
+                assert_eq!(path, "P");
+                assert_eq!(cycle, ["P", "Q", "R", "P"]);
+            }
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+        assert!(gen_device_with(&d, &opts).is_err());
+
+        let c = register("C", &[], "").replace("<register>",
+                                               "<register derivedFrom=\"C\">");
+        let xml = derived_device(&[("P", None, &c)]);
+        assert!(parse(&xml).is_err());
+    }
+
+    #[test]
+    fn derived_registers() {
+        let a = register("A", &[field("F", 0, 1, "")], "<size>16</size>");
+        let b = "<register derivedFrom=\"A\"><name>B</name>\
+                 <addressOffset>4</addressOffset></register>";
+        let xml = derived_device(&[("P", None, &format!("{}{}", a, b))]);
+        let (d, _) = parse(&xml).unwrap();
+
+        let b = &d.peripherals[0].registers.as_ref().unwrap()[1];
+        assert_eq!((&b.name[..], b.address_offset, b.size),
+                   ("B", 4, Some(16)));
+        assert_eq!(b.fields.as_ref().unwrap()[0].name, "F");
+    }
+
+    #[test]
+    fn expanded_peripheral_arrays() {
+        let uart = |name: &str, dim: &str| {
+            format!("<device><name>D</name><size>32</size><peripherals>\
+                     <peripheral><name>{}</name>{}\
+                     <baseAddress>0x1000</baseAddress><interrupt>\
+                     <name>UART</name><value>3</value></interrupt>\
+                     <registers>{}</registers></peripheral></peripherals>\
+                     </device>",
+                    name,
+                    dim,
+                    register("DR", &[field("D", 0, 8, "")], ""))
+        };
+        let xml = uart("UART%s",
+                       "<dim>3</dim><dimIncrement>0x400</dimIncrement>\
+                        <dimIndex>A,B,C</dimIndex>");
+        let (d, opts) = parse(&xml).unwrap();
+        let expanded = expand_peripheral_arrays(&d, &opts.peripheral_arrays)
+            .unwrap();
+        assert_eq!(expanded.peripherals
+                       .iter()
+                       .map(|p| (&p.name[..], p.base_address))
+                       .collect::<Vec<_>>(),
+                   [("UARTA", 0x1000), ("UARTB", 0x1400), ("UARTC", 0x1800)]);
+        assert_eq!(interrupts(&d, &opts).unwrap().len(), 1);
+        assert!(gen_device_with(&d, &opts).is_ok());
+
+        for dim in &["<dim>0</dim><dimIncrement>4</dimIncrement>",
+                     "<dim>2</dim>",
+                     "<dim>2</dim><dimIncrement>4</dimIncrement>\
+                      <dimIndex>A</dimIndex>"] {
+            assert!(parse(&uart("UART%s", dim)).is_err());
+        }
+        let dim = "<dim>2</dim><dimIncrement>4</dimIncrement>";
+        assert!(parse(&uart("UART", dim)).is_err());
+    }
+
+    #[test]
+    fn simulated_side_effects() {
+        let mwv = |v: &str| {
+            format!("<modifiedWriteValues>{}</modifiedWriteValues>", v)
+        };
+        let clear_on_read = "<readAction>clear</readAction>";
+        let fields = [field("UIF", 0, 1, &mwv("oneToClear")),
+                      field("OVR", 1, 1, clear_on_read),
+                      field("RO",
+                            2,
+                            1,
+                            &format!("<access>read-only</access>{}",
+                                     mwv("oneToClear"))),
+                      field("WO",
+                            3,
+                            1,
+                            &format!("<access>write-only</access>{}",
+                                     clear_on_read)),
+                      field("EN", 4, 1, "")];
+        let (d, r, extras) =
+            first_register(&device(&register("SR", &fields, "")));
+
+        let masks = side_effect_masks(&r, &extras, 32);
+        assert_eq!(masks.into_iter().collect::<Vec<_>>(),
+                   [("clear_on_read", 0b1010), ("one_to_clear", 0b0101)]);
+
+        // only the bits that can be accessed have side effects
+        let mut models = vec![];
+        register_models(&[r], &extras, 0, &d.defaults, &mut models).unwrap();
+        let model = models[0].as_str();
+        assert!(model.contains("write_mask : 0x1b ,"));
+        assert!(model.contains("one_to_clear : 0x1 ,"));
+        assert!(model.contains("clear_on_read : 0x2 ,"));
+    }
+}
@@ -1,12 +1,16 @@
 extern crate clap;
+extern crate quote;
 extern crate svd2rust;
 extern crate svd_parser as svd;
 
 use std::ascii::AsciiExt;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use quote::Tokens;
 
 fn main() {
     let matches = App::new("svd2rust")
@@ -20,47 +24,235 @@ fn main() {
         .arg(Arg::with_name("peripheral")
             .help("Pattern used to select a single peripheral")
             .value_name("PATTERN"))
+        .arg(Arg::with_name("device")
+            .help("Generate the register maps of all the peripherals")
+            .short("d")
+            .long("device")
+            .conflicts_with("peripheral"))
+        .arg(Arg::with_name("output-dir")
+            .help("Write the register maps of all the peripherals to DIR, one \
+                   file per peripheral")
+            .short("o")
+            .long("output-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .conflicts_with_all(&["peripheral", "device"]))
+        .arg(Arg::with_name("generic")
+            .help("Use a generic register type instead of one type per \
+                   register (requires --device or --output-dir)")
+            .long("generic"))
+        .arg(Arg::with_name("shared")
+            .help("Make all the register methods, including `write` and \
+                   `modify`, take `&self`; the register blocks are not \
+                   `Sync` (requires --device or --output-dir)")
+            .long("shared"))
+        .arg(Arg::with_name("self-contained")
+            .help("Don't depend on the `volatile_register` crate; the \
+                   register types are part of the generated code (requires \
+                   --device or --output-dir)")
+            .long("self-contained"))
+        .arg(Arg::with_name("volatile-path")
+            .help("Path of the crate, or of the module relative to the \
+                   crate root, that provides the `RO`, `RW` and `WO` register \
+                   types instead of `::volatile_register` (requires --device \
+                   or --output-dir)")
+            .long("volatile-path")
+            .takes_value(true)
+            .value_name("PATH")
+            .conflicts_with_all(&["shared", "self-contained"]))
+        .arg(Arg::with_name("bus")
+            .help("Read and write the registers through the `RegisterBus` \
+                   trait; the `mock` feature of the generated crate replaces \
+                   the hardware with an in-memory mock (requires --device or \
+                   --output-dir)")
+            .long("bus")
+            .conflicts_with("volatile-path"))
+        .arg(Arg::with_name("linux")
+            .help("Generate, behind the `linux` feature of the generated \
+                   crate, methods that map the register blocks from files \
+                   like /dev/mem or UIO devices (requires --device or \
+                   --output-dir)")
+            .long("linux"))
+        .arg(Arg::with_name("emit")
+            .help("Generate a linker script (fragment), or the declarations \
+                   of the symbols that the linker script defines, instead of \
+                   the register maps")
+            .long("emit")
+            .takes_value(true)
+            .value_name("KIND")
+            .possible_values(&["device-x", "linker", "externs"])
+            .conflicts_with_all(&["peripheral", "device", "output-dir"]))
         .version(concat!(env!("CARGO_PKG_VERSION"),
                          include_str!(concat!(env!("OUT_DIR"),
                                               "/commit-info.txt"))))
         .get_matches();
 
+    if let Err(e) = run(&matches) {
+        writeln!(io::stderr(), "error: {}", e).ok();
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let input = matches.value_of("input").unwrap();
     let xml = &mut String::new();
-    File::open(matches.value_of("input").unwrap())
-        .unwrap()
-        .read_to_string(xml)
-        .unwrap();
+    try!(File::open(input)
+        .and_then(|mut f| f.read_to_string(xml))
+        .map_err(|e| format!("couldn't read {}: {}", input, e)));
+
+    let (d, mut opts) = try!(svd2rust::parse(xml).map_err(|e| {
+        format!("couldn't parse {}: {}", input, e)
+    }));
+
+    match matches.value_of("emit") {
+        Some("device-x") => {
+            let device_x = try!(svd2rust::gen_device_x_with(&d, &opts)
+                .map_err(|e| e.to_string()));
+            print!("{}", device_x);
+            return Ok(());
+        }
+        Some("linker") => {
+            print!("{}",
+                   try!(svd2rust::gen_linker_script_with(&d, &opts)
+                       .map_err(|e| e.to_string())));
+            return Ok(());
+        }
+        Some("externs") => {
+            let externs = try!(svd2rust::gen_extern_statics_with(&d, &opts)
+                .map_err(|e| e.to_string()));
+            print!("{}", svd2rust::pretty_print(&externs));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    opts.generic = matches.is_present("generic");
+    opts.shared = matches.is_present("shared");
+    opts.self_contained = matches.is_present("self-contained");
+    opts.volatile_path = matches.value_of("volatile-path").map(String::from);
+    opts.bus = matches.is_present("bus");
+    opts.linux = matches.is_present("linux");
+    for flag in &["generic",
+                  "shared",
+                  "self-contained",
+                  "volatile-path",
+                  "bus",
+                  "linux"] {
+        if matches.is_present(flag) && !matches.is_present("device") &&
+           !matches.is_present("output-dir") {
+            return Err(format!("`--{}` requires `--device` or `--output-dir`",
+                               flag));
+        }
+    }
+
+    if let Some(dir) = matches.value_of("output-dir") {
+        let files = try!(svd2rust::gen_device_files_with(&d, &opts)
+            .map_err(|e| e.to_string()));
+        try!(fs::create_dir_all(dir)
+            .map_err(|e| format!("couldn't create {}: {}", dir, e)));
+        let dir = Path::new(dir);
+        let names = files.iter()
+            .map(|&(ref name, _)| &name[..])
+            .collect::<Vec<_>>();
+        try!(remove_stale_files(dir, &names));
+        for &(ref name, ref contents) in &files {
+            try!(write_file(&dir.join(name),
+                            &svd2rust::pretty_print(contents)));
+        }
+        let manifest = names.iter()
+            .map(|name| format!("{}\n", name))
+            .collect::<String>();
+        try!(write_file(&dir.join(MANIFEST), &manifest));
+        return Ok(());
+    }
+
+    if matches.is_present("device") {
+        let device = try!(svd2rust::gen_device_with(&d, &opts)
+            .map_err(|e| e.to_string()));
+        print!("{}", svd2rust::pretty_print(&device));
+        for w in try!(svd2rust::warnings(&d).map_err(|e| e.to_string())) {
+            writeln!(io::stderr(), "WARNING {}", w).ok();
+        }
+        return Ok(());
+    }
 
-    let d = svd::parse(xml);
+    let expanded =
+        try!(svd2rust::expand_peripheral_arrays(&d, &opts.peripheral_arrays)
+            .map_err(|e| e.to_string()));
     match matches.value_of("peripheral") {
         None => {
-            for peripheral in &d.peripherals {
+            for peripheral in &expanded.peripherals {
                 println!("const {}: usize = 0x{:08x};",
                          peripheral.name,
                          peripheral.base_address);
             }
         }
         Some(pattern) => {
-            if let Some(peripheral) = find_peripheral(&d, |n| n == pattern)
-                .or_else(|| find_peripheral(&d, |n| n.contains(pattern))) {
-                if let Some(base_peripheral) =
-                    peripheral.derived_from
-                        .as_ref()
-                        .and_then(|bn| {
-                            find_peripheral(&d,
-                                            |n| n == bn.to_ascii_lowercase())
-                        }) {
-                    let merged_peripheral = merge(peripheral, base_peripheral);
-                    println!("{}",
-                             gen_peripheral_desc(&merged_peripheral,
-                                                 &d.defaults));
-                } else {
-                    println!("{}",
-                             gen_peripheral_desc(peripheral, &d.defaults));
-                }
-            }
+            let peripheral = try!(find_peripheral(&expanded, |n| n == pattern)
+                .or_else(|| {
+                    find_peripheral(&expanded, |n| n.contains(pattern))
+                })
+                .ok_or_else(|| {
+                    format!("no peripheral matches `{}`", pattern)
+                }));
+
+            print!("{}", try!(gen_peripheral_desc(&d, peripheral, &opts)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` to `path` unless the file already has those contents
+///
+/// This leaves the modification time of the files that didn't change intact,
+/// so regenerating the register maps doesn't trigger a rebuild.
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    let current = &mut String::new();
+    if File::open(path)
+        .and_then(|mut f| f.read_to_string(current))
+        .is_ok() && current == contents {
+        return Ok(());
+    }
+
+    File::create(path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+}
+
+/// Name of the file, in the output directory, that lists the files that
+/// svd2rust generated there, one per line
+const MANIFEST: &'static str = ".svd2rust-files";
+
+/// Removes the files that the manifest in `dir`, which a previous run wrote,
+/// lists but that are no longer part of `names`
+///
+/// Only the files that the manifest lists are removed, so the files that
+/// svd2rust didn't generate are left alone.
+fn remove_stale_files(dir: &Path, names: &[&str]) -> Result<(), String> {
+    let manifest = &mut String::new();
+    if File::open(dir.join(MANIFEST))
+        .and_then(|mut f| f.read_to_string(manifest))
+        .is_err() {
+        return Ok(());
+    }
+
+    for name in manifest.lines().map(|l| l.trim()) {
+        // NOTE never leave `dir`, even if the manifest was edited by hand
+        if name.is_empty() || name.contains('/') || name.contains('\\') ||
+           name == ".." || names.contains(&name) {
+            continue;
+        }
+
+        let path = dir.join(name);
+        if path.is_file() {
+            try!(fs::remove_file(&path).map_err(|e| {
+                format!("couldn't remove {}: {}", path.display(), e)
+            }));
         }
     }
+
+    Ok(())
 }
 
 fn find_peripheral<F: Fn(&str) -> bool>(device: &svd::Device,
@@ -69,27 +261,15 @@ fn find_peripheral<F: Fn(&str) -> bool>(device: &svd::Device,
     device.peripherals.iter().find(|x| matcher(&x.name.to_ascii_lowercase()))
 }
 
-fn gen_peripheral_desc(p: &svd::Peripheral, def: &svd::Defaults) -> String {
-    svd2rust::gen_peripheral(p, &def)
-        .iter()
-        .map(|i| i.to_string())
-        .collect::<Vec<_>>()
-        .join("\n\n")
-}
+fn gen_peripheral_desc(d: &svd::Device,
+                       p: &svd::Peripheral,
+                       opts: &svd2rust::Options)
+                       -> Result<String, String> {
+    let items = try!(svd2rust::gen_peripheral_with(d, p, opts)
+        .map_err(|e| e.to_string()));
 
-fn merge(p: &svd::Peripheral, bp: &svd::Peripheral) -> svd::Peripheral {
-    assert!(p.registers.is_none() || bp.registers.is_none(),
-            "Either {} registers or {} registers must be absent in SVD",
-            p.name,
-            bp.name);
-
-    svd::Peripheral {
-        name: p.name.clone(),
-        base_address: p.base_address,
-        derived_from: None,
-        group_name: p.group_name.clone().or_else(|| bp.group_name.clone()),
-        description: p.description.clone().or_else(|| bp.description.clone()),
-        interrupt: p.interrupt.clone().or_else(|| bp.interrupt.clone()),
-        registers: p.registers.clone().or_else(|| bp.registers.clone()),
-    }
+    let mut tokens = Tokens::new();
+    tokens.append_all(items);
+
+    Ok(svd2rust::pretty_print(&tokens))
 }
@@ -0,0 +1,860 @@
+//! Pretty printer for the generated code
+//!
+//! `quote` renders the generated code as a single line where every token is
+//! separated by a space. This module turns that into indented, line broken
+//! Rust source without depending on `rustfmt`. The output only depends on the
+//! input tokens so it's the same across runs.
+
+use std::cmp;
+
+use quote::Tokens;
+
+/// Maximum length of a line before its last list gets one element per line,
+/// or its doc comment gets wrapped
+const MAX_WIDTH: usize = 100;
+
+/// Keywords that are never followed by the arguments of a call or an index
+const KEYWORDS: &'static [&'static str] = &["as", "const", "else", "for", "if",
+                                             "impl", "in", "let", "match",
+                                             "mut", "return", "where",
+                                             "while"];
+
+/// Tokens that continue a statement after a brace delimited group
+const CONTINUATIONS: &'static [&'static str] = &[",", ".", ";", "?", "as",
+                                                  "else"];
+
+/// Token tree
+#[derive(Clone)]
+enum Tt {
+    /// A single token, e.g. `foo`, `::` or `"bar"`
+    Token(String),
+    /// A delimited group of tokens, e.g. `( .. )`; the `char` is the opening
+    /// delimiter
+    Group(char, Vec<Tt>),
+}
+
+impl Tt {
+    fn is(&self, token: &str) -> bool {
+        match *self {
+            Tt::Token(ref t) => t == token,
+            Tt::Group(..) => false,
+        }
+    }
+
+    fn is_group(&self, delimiter: char) -> bool {
+        match *self {
+            Tt::Token(_) => false,
+            Tt::Group(d, _) => d == delimiter,
+        }
+    }
+}
+
+/// What a brace delimited group contains
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// Items, e.g. the contents of a module, an `impl` block or a file
+    Items,
+    /// Statements, e.g. the body of a function
+    Statements,
+    /// Comma separated elements, e.g. the fields of a `struct`
+    Fields,
+    /// The arms of a `match` expression
+    Arms,
+}
+
+/// Formats `tokens` as Rust source code
+pub fn pretty_print(tokens: &Tokens) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+    };
+
+    printer.block(&parse(&lex(tokens.as_str())), Kind::Items);
+    printer.out
+}
+
+/// Splits the output of `quote` in tokens
+fn lex(s: &str) -> Vec<String> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        if let Some(end) = end_of_string(&chars, i) {
+            i = end;
+        } else if chars[i] == '\'' && chars.get(i + 1) == Some(&'\\') {
+            // escaped character literal, e.g. `'\n'`
+            i += 3;
+            while i < chars.len() && chars[i] != '\'' {
+                i += 1;
+            }
+            i += 1;
+        } else if chars[i] == '\'' && chars.get(i + 2) == Some(&'\'') {
+            // character literal, e.g. `'a'`
+            i += 3;
+        } else {
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+        }
+
+        tokens.push(chars[start..cmp::min(i, chars.len())]
+            .iter()
+            .cloned()
+            .collect());
+    }
+
+    tokens
+}
+
+/// If a string literal starts at `start`, returns the position right after it
+fn end_of_string(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    if chars[i] == 'b' {
+        i += 1;
+    }
+
+    if chars.get(i) == Some(&'r') {
+        // raw string literal, e.g. `r#"foo"#`
+        i += 1;
+        let mut hashes = 0;
+        while chars.get(i) == Some(&'#') {
+            hashes += 1;
+            i += 1;
+        }
+
+        if chars.get(i) != Some(&'"') {
+            return None;
+        }
+
+        i += 1;
+        while i < chars.len() {
+            if chars[i] == '"' &&
+               chars[i + 1..].iter().take_while(|c| **c == '#').count() >=
+               hashes {
+                return Some(i + 1 + hashes);
+            }
+            i += 1;
+        }
+
+        Some(i)
+    } else if chars.get(i) == Some(&'"') {
+        i += 1;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => i += 2,
+                '"' => return Some(i + 1),
+                _ => i += 1,
+            }
+        }
+
+        Some(i)
+    } else {
+        None
+    }
+}
+
+/// Groups the delimited tokens
+fn parse(tokens: &[String]) -> Vec<Tt> {
+    let mut stack = vec![('\0', vec![])];
+
+    for token in tokens {
+        match &token[..] {
+            "(" | "[" | "{" => {
+                stack.push((token.chars().next().unwrap(), vec![]));
+            }
+            ")" | "]" | "}" if stack.len() > 1 => {
+                let (delimiter, tts) = stack.pop().unwrap();
+                stack.last_mut().unwrap().1.push(Tt::Group(delimiter, tts));
+            }
+            _ => stack.last_mut().unwrap().1.push(Tt::Token(token.clone())),
+        }
+    }
+
+    // NOTE unbalanced delimiters; this can't happen with the output of `quote`
+    while stack.len() > 1 {
+        let (delimiter, tts) = stack.pop().unwrap();
+        stack.last_mut().unwrap().1.push(Tt::Group(delimiter, tts));
+    }
+
+    stack.pop().unwrap().1
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    fn line(&mut self, line: &str) {
+        if line.is_empty() {
+            self.out.push('\n');
+        } else {
+            for _ in 0..self.indent {
+                self.out.push_str("    ");
+            }
+            self.out.push_str(line);
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints the contents of a brace delimited group, or of a whole file
+    fn block(&mut self, tts: &[Tt], kind: Kind) {
+        let mut i = 0;
+        let mut first = true;
+
+        while i < tts.len() {
+            if kind == Kind::Items && !first {
+                self.line("");
+            }
+            first = false;
+
+            // inner attributes stand on their own
+            if tts[i].is("#") &&
+               tts.get(i + 1).map(|tt| tt.is("!")) == Some(true) &&
+               tts.get(i + 2).map(|tt| tt.is_group('[')) == Some(true) {
+                self.attribute(&tts[i + 2], true);
+                i += 3;
+                continue;
+            }
+
+            while i < tts.len() && tts[i].is("#") &&
+                  tts.get(i + 1).map(|tt| tt.is_group('[')) == Some(true) {
+                self.attribute(&tts[i + 1], false);
+                i += 2;
+            }
+
+            i = self.statement(tts, i, kind);
+        }
+    }
+
+    /// Prints the statement that starts at `tts[i]`; returns the position of
+    /// the next statement
+    fn statement(&mut self, tts: &[Tt], mut i: usize, kind: Kind) -> usize {
+        let mut line = vec![];
+
+        while i < tts.len() {
+            let tt = &tts[i];
+            i += 1;
+
+            if tt.is(";") ||
+               (tt.is(",") && (kind == Kind::Fields || kind == Kind::Arms)) {
+                line.push(tt.clone());
+                break;
+            }
+
+            let contents = match *tt {
+                Tt::Group('{', ref contents) => contents,
+                _ => {
+                    line.push(tt.clone());
+                    continue;
+                }
+            };
+
+            match brace_kind(&line, contents, self.indent) {
+                Some(inner) => {
+                    self.header(&line);
+                    self.indent += 1;
+                    self.block(contents, inner);
+                    self.indent -= 1;
+
+                    line = vec![Tt::Token(String::from("}"))];
+                }
+                None => line.push(tt.clone()),
+            }
+
+            // a group ends the statement unless it's followed by e.g. `else`,
+            // `;` or `,`
+            match tts.get(i) {
+                Some(&Tt::Token(ref t)) if CONTINUATIONS.contains(&&t[..]) => {}
+                _ => break,
+            }
+        }
+
+        if !line.is_empty() {
+            self.wrapped(&line);
+        }
+
+        i
+    }
+
+    /// Prints `line`; if it's too long, its last list gets one element per
+    /// line
+    fn wrapped(&mut self, line: &[Tt]) {
+        let joined = join(line);
+        if self.indent * 4 + joined.len() <= MAX_WIDTH {
+            return self.line(&joined);
+        }
+
+        let list = line.iter().rposition(|tt| match *tt {
+            Tt::Group('(', ref tts) |
+            Tt::Group('[', ref tts) => tts.iter().any(|tt| tt.is(",")),
+            _ => false,
+        });
+
+        let pos = match list {
+            Some(pos) => pos,
+            None => return self.line(&joined),
+        };
+
+        let (delimiter, tts) = match line[pos] {
+            Tt::Group(delimiter, ref tts) => (delimiter, tts),
+            _ => unreachable!(),
+        };
+
+        let mut open = line[..pos].to_vec();
+        open.push(Tt::Token(delimiter.to_string()));
+        self.line(&join(&open));
+        self.indent += 1;
+        for element in tts.split(|tt| tt.is(",")) {
+            if !element.is_empty() {
+                // the element may itself be too long, e.g. a struct literal
+                let mut element = element.to_vec();
+                element.push(Tt::Token(String::from(",")));
+                self.statement(&element, 0, Kind::Statements);
+            }
+        }
+        self.indent -= 1;
+
+        let mut close = vec![Tt::Token(closing(delimiter).to_string())];
+        close.extend(line[pos + 1..].iter().cloned());
+        self.line(&join(&close));
+    }
+
+    /// Prints the part of a statement that precedes a brace delimited group
+    /// plus the opening brace; `where` clauses get one line per predicate
+    fn header(&mut self, line: &[Tt]) {
+        let where_ = match line.iter().position(|tt| tt.is("where")) {
+            Some(pos) => pos,
+            None => {
+                let joined = join(line);
+                if joined.is_empty() {
+                    return self.line("{");
+                } else {
+                    return self.line(&format!("{} {{", joined));
+                }
+            }
+        };
+
+        self.line(&join(&line[..where_]));
+        self.line("where");
+        self.indent += 1;
+        let mut depth = 0;
+        let mut predicate = vec![];
+        for tt in &line[where_ + 1..] {
+            match *tt {
+                Tt::Token(ref t) if t == "<" => depth += 1,
+                Tt::Token(ref t) if t == ">" => depth -= 1,
+                Tt::Token(ref t) if t == "," && depth == 0 => {
+                    self.line(&format!("{},", join(&predicate)));
+                    predicate.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            predicate.push(tt.clone());
+        }
+        if !predicate.is_empty() {
+            self.line(&format!("{},", join(&predicate)));
+        }
+        self.indent -= 1;
+        self.line("{");
+    }
+
+    /// Prints an attribute; `doc` attributes become doc comments
+    fn attribute(&mut self, tt: &Tt, inner: bool) {
+        let tts = match *tt {
+            Tt::Group(_, ref tts) => tts,
+            Tt::Token(_) => unreachable!(),
+        };
+
+        let doc = match (tts.get(0), tts.get(1), tts.get(2), tts.len()) {
+            (Some(d), Some(eq), Some(&Tt::Token(ref lit)), 3) if d.is("doc") &&
+                                                                eq.is("=") => {
+                unescape(lit)
+            }
+            _ => None,
+        };
+
+        match doc {
+            Some(doc) => {
+                let prefix = if inner { "//!" } else { "///" };
+                let width = MAX_WIDTH.saturating_sub(self.indent * 4 + 4);
+                let mut code = false;
+                for line in doc.split('\n') {
+                    let body = if line.starts_with(' ') {
+                        &line[1..]
+                    } else {
+                        line
+                    };
+
+                    if body.trim_left().starts_with("```") {
+                        code = !code;
+                    }
+
+                    if body.is_empty() {
+                        self.line(prefix);
+                    } else if code || !wraps(body) {
+                        self.line(&format!("{} {}", prefix, body));
+                    } else {
+                        for chunk in wrap(body, width) {
+                            self.line(&format!("{} {}", prefix, chunk));
+                        }
+                    }
+                }
+            }
+            None => {
+                let bang = if inner { "!" } else { "" };
+                self.line(&format!("#{}[{}]", bang, join(tts)));
+            }
+        }
+    }
+}
+
+/// Decides whether a brace delimited group must span several lines, and if
+/// so what it contains
+///
+/// `line` is the part of the statement that precedes the group.
+fn brace_kind(line: &[Tt], contents: &[Tt], indent: usize) -> Option<Kind> {
+    if contents.is_empty() {
+        return None;
+    }
+
+    let has = |keyword: &str| line.iter().any(|tt| tt.is(keyword));
+
+    if has("struct") || has("enum") || has("union") {
+        Some(Kind::Fields)
+    } else if has("mod") || has("impl") || has("trait") {
+        Some(Kind::Items)
+    } else if has("extern") {
+        // foreign items are listed without blank lines in between
+        Some(Kind::Statements)
+    } else if has("match") {
+        Some(Kind::Arms)
+    } else if has("fn") {
+        Some(Kind::Statements)
+    } else {
+        let statements = contents.iter().any(|tt| tt.is(";"));
+        let fields = contents.iter().any(|tt| tt.is(",") || tt.is(":"));
+        let control = contents.iter().any(|tt| tt.is("if") || tt.is("match"));
+        let inline = join(line).len() + join(contents).len() + 6;
+
+        if !has_statements(contents) && !control &&
+           indent * 4 + inline <= MAX_WIDTH {
+            None
+        } else if !statements && fields {
+            Some(Kind::Fields)
+        } else {
+            Some(Kind::Statements)
+        }
+    }
+}
+
+/// Whether `tts` contains statements, at any depth
+fn has_statements(tts: &[Tt]) -> bool {
+    tts.iter().any(|tt| match *tt {
+        Tt::Token(ref t) => t == ";",
+        Tt::Group('[', _) => false,
+        Tt::Group(_, ref tts) => has_statements(tts),
+    })
+}
+
+fn closing(delimiter: char) -> char {
+    match delimiter {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+fn is_ident(t: &str) -> bool {
+    t.chars().next().map(|c| c.is_alphabetic() || c == '_') == Some(true)
+}
+
+fn is_keyword(t: &str) -> bool {
+    KEYWORDS.contains(&t)
+}
+
+/// Whether the previous token ends an operand, e.g. `x`, `1` or `)`
+fn ends_operand(prev: &str) -> bool {
+    (is_ident(prev) && !is_keyword(prev)) ||
+    prev.chars().next().map(|c| c.is_digit(10)) == Some(true) ||
+    prev.ends_with('"') || prev == ")" || prev == "]" || prev == "}"
+}
+
+/// Joins the tokens of `tts` in a single line
+fn join(tts: &[Tt]) -> String {
+    let mut out = String::new();
+    // previous token and whether it's a prefix, e.g. unary `&` or generic `<`
+    let mut prev: Option<(String, bool)> = None;
+    // open generic brackets
+    let mut generics = 0;
+
+    for (i, tt) in tts.iter().enumerate() {
+        let next = tts.get(i + 1);
+
+        let (text, first, last) = match *tt {
+            Tt::Token(ref t) => (t.clone(), t.clone(), t.clone()),
+            Tt::Group(delimiter, ref tts) => {
+                let close = closing(delimiter);
+                let text = if tts.is_empty() {
+                    format!("{}{}", delimiter, close)
+                } else if delimiter == '{' {
+                    format!("{{ {} }}", join(tts))
+                } else {
+                    format!("{}{}{}", delimiter, join(tts), close)
+                };
+                (text, delimiter.to_string(), close.to_string())
+            }
+        };
+
+        let mut prefix = false;
+        let space = match prev {
+            None => false,
+            Some((ref p, p_prefix)) => {
+                let p = &p[..];
+                let first = &first[..];
+                if p_prefix {
+                    false
+                } else if first == "<" && is_generic(p, next) {
+                    generics += 1;
+                    prefix = true;
+                    false
+                } else if (first == ">" || first == ">>") && generics > 0 {
+                    generics -= cmp::min(generics, first.len());
+                    false
+                } else if first == "!" && is_ident(p) &&
+                          next.map(|tt| match *tt {
+                        Tt::Group(..) => true,
+                        Tt::Token(_) => false,
+                    }) == Some(true) {
+                    // macro invocation
+                    prefix = true;
+                    false
+                } else {
+                    match first {
+                        "," | ";" | "." | "?" | ":" | ")" | "]" => false,
+                        "::" => !((is_ident(p) && !is_keyword(p)) || p == ">"),
+                        "(" | "[" => !(ends_operand(p) || p == ">" || p == "#"),
+                        _ => !(p == "::" || p == "." || p == "#"),
+                    }
+                }
+            }
+        };
+
+        if space {
+            out.push(' ');
+        }
+        out.push_str(&text);
+
+        if let Tt::Token(ref t) = *tt {
+            let after_operand = prev.as_ref()
+                .map(|&(ref p, _)| ends_operand(p))
+                .unwrap_or(false);
+            if (t == "&" || t == "*" || t == "-" || t == "!") &&
+               !after_operand {
+                prefix = true;
+            }
+        }
+
+        prev = Some((last, prefix));
+    }
+
+    out
+}
+
+/// Whether the `<` that follows `prev` opens a list of generic parameters
+fn is_generic(prev: &str, next: Option<&Tt>) -> bool {
+    if prev == "impl" || prev == "for" || prev == "::" {
+        return true;
+    }
+
+    if !is_ident(prev) || is_keyword(prev) {
+        return false;
+    }
+
+    let next = match next {
+        Some(&Tt::Token(ref t)) => &t[..],
+        _ => return false,
+    };
+
+    prev.chars().next().map(|c| c.is_uppercase()) == Some(true) ||
+    next.starts_with('\'') ||
+    next.chars().next().map(|c| c.is_uppercase()) == Some(true)
+}
+
+/// Whether the doc comment `line` can be wrapped; code, headings and table
+/// rows can't
+fn wraps(line: &str) -> bool {
+    !line.starts_with(' ') && !line.starts_with('#') && !line.starts_with('|')
+}
+
+/// Splits `line` in lines of at most `width` characters; words that are
+/// longer than that get a line of their own
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current = String::new();
+
+    for word in line.split_whitespace() {
+        if !current.is_empty() && current.len() + 1 + word.len() > width {
+            lines.push(current);
+            current = String::new();
+        }
+
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+/// Returns the contents of a string literal
+fn unescape(lit: &str) -> Option<String> {
+    if lit.starts_with('r') {
+        let hashes = lit[1..].chars().take_while(|c| *c == '#').count();
+        return Some(lit[2 + hashes..lit.len() - 1 - hashes].to_owned());
+    }
+
+    if !lit.starts_with('"') {
+        return None;
+    }
+
+    let mut out = String::new();
+    let mut chars = lit[1..lit.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('u') => {
+                let hex = chars.by_ref()
+                    .skip(1)
+                    .take_while(|c| *c != '}')
+                    .collect::<String>();
+                match u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(::std::char::from_u32) {
+                    Some(c) => out.push(c),
+                    None => return None,
+                }
+            }
+            Some(c) => out.push(c),
+            None => return None,
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::{ToTokens, Tokens};
+    use syn::{self, DelimToken, Lit, Token, TokenTree};
+
+    use super::{MAX_WIDTH, pretty_print};
+
+    /// Splits the Rust `source` in token trees; its doc comments become
+    /// `doc` attributes, like the ones that `quote!` generates
+    fn tokenize(source: &str) -> Vec<TokenTree> {
+        let mut undoc = String::new();
+        for line in source.lines() {
+            let trimmed = line.trim_left();
+            if trimmed.starts_with("//!") {
+                undoc.push_str(&format!("#![doc = {:?}]\n", &trimmed[3..]));
+            } else if trimmed.starts_with("///") {
+                undoc.push_str(&format!("#[doc = {:?}]\n", &trimmed[3..]));
+            } else {
+                undoc.push_str(line);
+                undoc.push('\n');
+            }
+        }
+
+        syn::parse_token_trees(&undoc).unwrap()
+    }
+
+    /// Flattens `tts`; the `doc` attributes become the words they contain so
+    /// that wrapped doc comments compare equal to the original ones
+    fn words(tts: &[TokenTree], out: &mut Vec<String>) {
+        let mut i = 0;
+        while i < tts.len() {
+            if let Some((n, doc)) = doc(&tts[i..]) {
+                out.extend(doc.split_whitespace().map(|w| w.to_owned()));
+                i += n;
+                continue;
+            }
+
+            match tts[i] {
+                TokenTree::Token(ref t) => out.push(format!("{:?}", t)),
+                TokenTree::Delimited(ref d) => {
+                    out.push(format!("{:?}", d.delim));
+                    words(&d.tts, out);
+                    out.push(String::from("end"));
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// If `tts` starts with a `doc` attribute, returns how many token trees
+    /// it spans plus its text
+    fn doc(tts: &[TokenTree]) -> Option<(usize, String)> {
+        let bang = match tts.get(1) {
+            Some(&TokenTree::Token(Token::Not)) => 1,
+            _ => 0,
+        };
+
+        let attribute = match (&tts[0], tts.get(1 + bang)) {
+            (&TokenTree::Token(Token::Pound),
+             Some(&TokenTree::Delimited(ref d))) if d.delim ==
+                                                     DelimToken::Bracket => {
+                &d.tts
+            }
+            _ => return None,
+        };
+
+        if attribute.len() != 3 ||
+           attribute[0] != TokenTree::Token(Token::Ident("doc".into())) ||
+           attribute[1] != TokenTree::Token(Token::Eq) {
+            return None;
+        }
+
+        match attribute[2] {
+            TokenTree::Token(Token::Literal(Lit::Str(ref s, _))) => {
+                Some((2 + bang, s.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Turns the token trees of `source` back into `Tokens`
+    fn requote(source: &str) -> Tokens {
+        fn append(tts: &[TokenTree], tokens: &mut Tokens) {
+            for tt in tts {
+                match *tt {
+                    TokenTree::Token(ref t) => {
+                        let mut token = Tokens::new();
+                        t.to_tokens(&mut token);
+                        tokens.append(token.as_str());
+                    }
+                    TokenTree::Delimited(ref d) => {
+                        let (open, close) = match d.delim {
+                            DelimToken::Paren => ("(", ")"),
+                            DelimToken::Bracket => ("[", "]"),
+                            DelimToken::Brace => ("{", "}"),
+                        };
+                        tokens.append(open);
+                        append(&d.tts, tokens);
+                        tokens.append(close);
+                    }
+                }
+            }
+        }
+
+        let mut tokens = Tokens::new();
+        append(&tokenize(source), &mut tokens);
+        tokens
+    }
+
+    /// Checks that pretty printing `tokens` preserves them, and that pretty
+    /// printing the output again doesn't change it
+    fn round_trip(tokens: &Tokens) -> String {
+        let pretty = pretty_print(tokens);
+
+        let (mut before, mut after) = (vec![], vec![]);
+        words(&tokenize(tokens.as_str()), &mut before);
+        words(&tokenize(&pretty), &mut after);
+        assert_eq!(before, after, "{}", pretty);
+
+        assert_eq!(pretty_print(&requote(&pretty)), pretty);
+
+        pretty
+    }
+
+    #[test]
+    fn strings() {
+        round_trip(&quote! {
+            const S: &'static str = "a \"quoted\" { string ( with [ groups";
+            const R: &'static str = r#"raw "string" }"#;
+            const B: &'static [u8] = b"bytes ; ,";
+            const C: [char; 3] = ['{', '\'', '"'];
+        });
+    }
+
+    #[test]
+    fn lifetimes() {
+        round_trip(&quote! {
+            pub struct Bytes<'a> {
+                bytes: &'a [u8],
+                name: &'static str,
+            }
+
+            impl<'a, 'b: 'a> From<&'b [u8]> for Bytes<'a> {
+                fn from(bytes: &'b [u8]) -> Bytes<'a> {
+                    Bytes { bytes: bytes, name: "bytes" }
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn doc_attributes() {
+        let long = "A doc comment that is long enough that it doesn't fit in \
+                    a single line of the generated code, even at the top \
+                    level, so it's wrapped at word boundaries";
+        let code = "```\nlet code = \"blocks, like this one, which is \
+                    longer than a line, are never wrapped\";\n```";
+        let pretty = round_trip(&quote! {
+            #![doc = "Crate documentation"]
+
+            #[doc = #long]
+            #[doc = ""]
+            #[doc = #code]
+            pub mod foo {
+                #[doc = #long]
+                pub struct Bar;
+            }
+        });
+
+        assert!(pretty.starts_with("//! Crate documentation\n"));
+        for line in pretty.lines().filter(|l| !l.contains("let code")) {
+            assert!(line.len() <= MAX_WIDTH, "{}", line);
+        }
+        assert_eq!(pretty.lines().filter(|l| l.contains("let code")).count(),
+                   1);
+    }
+
+    #[test]
+    fn nested_groups() {
+        round_trip(&quote! {
+            fn f(x: u32) -> Option<(u32, [u8; 2])> {
+                let v = [(1, [2, 3]), (4, [5, 6])];
+                if x > 1 {
+                    Some((x, [v[0].1[0], { v[1].1[1] }]))
+                } else {
+                    match x {
+                        0 => None,
+                        _ => { g(&v[..], |(a, b)| (b, a)); None }
+                    }
+                }
+            }
+        });
+    }
+}